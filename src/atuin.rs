@@ -0,0 +1,186 @@
+use crate::config::expand_home;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+
+/// The settings from Atuin's own `config.toml` that affect atuin-z. All
+/// other keys are ignored.
+#[derive(Debug, Default)]
+pub struct Settings {
+    /// Location of the history database, with `~` expanded. Defaults to
+    /// `history.db` in Atuin's `data_dir` if that is set.
+    pub db_path: Option<PathBuf>,
+    /// Regexes for commands Atuin does not record.
+    pub history_filter: Vec<String>,
+    /// Regexes for working directories Atuin does not record.
+    pub cwd_filter: Vec<String>,
+}
+
+/// Resolve the path to Atuin's config file.
+///
+/// Priority:
+/// 1. `ATUIN_CONFIG_DIR` / config.toml
+/// 2. `XDG_CONFIG_HOME` / atuin / config.toml
+/// 3. ~/.config/atuin/config.toml
+pub fn config_path() -> Result<PathBuf> {
+    if let Ok(dir) = std::env::var("ATUIN_CONFIG_DIR") {
+        return Ok(PathBuf::from(dir).join("config.toml"));
+    }
+
+    let base = if let Ok(xdg) = std::env::var("XDG_CONFIG_HOME") {
+        PathBuf::from(xdg)
+    } else {
+        let home = dirs::home_dir().context("could not determine home directory")?;
+        home.join(".config")
+    };
+    Ok(base.join("atuin").join("config.toml"))
+}
+
+/// Load Atuin's settings. A missing config file yields the defaults.
+///
+/// Atuin's config is not atuin-z's to validate: settings that are malformed,
+/// or the whole file if it does not parse, are left at their defaults with a
+/// warning.
+pub fn load() -> Result<Settings> {
+    let path = config_path()?;
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read Atuin config file: {}", path.display()))?;
+    let (settings, warnings) = parse(&content);
+    for warning in warnings {
+        eprintln!(
+            "atuin-z: warning: Atuin config file {}: {warning}",
+            path.display()
+        );
+    }
+    Ok(settings)
+}
+
+/// Parse Atuin's config, returning warnings about what had to be ignored.
+fn parse(content: &str) -> (Settings, Vec<String>) {
+    let mut warnings = Vec::new();
+    let table: toml::Table = match content.parse() {
+        Ok(table) => table,
+        Err(err) => {
+            warnings.push(format!("ignoring the file: {}", err.to_string().trim_end()));
+            return (Settings::default(), warnings);
+        }
+    };
+    let mut path = |key: &str| {
+        let path: PathBuf = get(&table, key, &mut warnings)?;
+        expand_home(&path)
+            .map_err(|err| warnings.push(format!("ignoring `{key}`: {err:#}")))
+            .ok()
+    };
+    let db_path = path("db_path");
+    let data_dir = path("data_dir");
+    let mut regexes = |key: &str| {
+        let mut regexes: Vec<String> = get(&table, key, &mut warnings).unwrap_or_default();
+        regexes.retain(|re| match regex::Regex::new(re) {
+            Ok(_) => true,
+            Err(err) => {
+                warnings.push(format!("ignoring invalid regex in `{key}`: {err}"));
+                false
+            }
+        });
+        regexes
+    };
+    let settings = Settings {
+        db_path: db_path.or_else(|| data_dir.map(|dir| dir.join("history.db"))),
+        history_filter: regexes("history_filter"),
+        cwd_filter: regexes("cwd_filter"),
+    };
+    (settings, warnings)
+}
+
+/// The value of `key` in `table`, or `None` with a warning if it has the
+/// wrong type.
+fn get<T: DeserializeOwned>(
+    table: &toml::Table,
+    key: &str,
+    warnings: &mut Vec<String>,
+) -> Option<T> {
+    match table.get(key)?.clone().try_into() {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warnings.push(format!("ignoring `{key}`: {}", err.to_string().trim_end()));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_relevant_keys_and_ignores_the_rest() {
+        let (settings, warnings) = parse(
+            r#"
+            auto_sync = true
+            sync_frequency = "10m"
+            db_path = "/data/atuin/history.db"
+            history_filter = ["^secret-cmd", "^innocuous-cmd .*--secret=.+"]
+            cwd_filter = ["^/very/secret/area"]
+
+            [stats]
+            common_prefix = ["sudo"]
+            "#,
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_eq!(
+            settings.db_path,
+            Some(PathBuf::from("/data/atuin/history.db"))
+        );
+        assert_eq!(settings.history_filter.len(), 2);
+        assert_eq!(settings.cwd_filter, vec!["^/very/secret/area".to_string()]);
+    }
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let (settings, _) = parse("");
+        assert_eq!(settings.db_path, None);
+        assert!(settings.history_filter.is_empty());
+        assert!(settings.cwd_filter.is_empty());
+    }
+
+    #[test]
+    fn db_path_expands_home() {
+        let (settings, _) = parse("db_path = \"~/atuin.db\"");
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(settings.db_path, Some(home.join("atuin.db")));
+    }
+
+    #[test]
+    fn db_path_defaults_to_data_dir() {
+        let (settings, _) = parse("data_dir = \"~/atuin-data\"");
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            settings.db_path,
+            Some(home.join("atuin-data").join("history.db"))
+        );
+
+        let (settings, _) = parse("data_dir = \"/data\"\ndb_path = \"/elsewhere/history.db\"");
+        assert_eq!(
+            settings.db_path,
+            Some(PathBuf::from("/elsewhere/history.db"))
+        );
+    }
+
+    #[test]
+    fn malformed_settings_fall_back_to_defaults() {
+        let (settings, warnings) = parse(
+            "history_filter = \"not a list\"\ncwd_filter = [\"^/ok\", \"(\"]\ndb_path = 42\ndata_dir = \"/data\"",
+        );
+        assert!(settings.history_filter.is_empty());
+        assert_eq!(settings.cwd_filter, vec!["^/ok".to_string()]);
+        assert_eq!(settings.db_path, Some(PathBuf::from("/data/history.db")));
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+
+        let (settings, warnings) = parse("db_path = ");
+        assert_eq!(settings.db_path, None);
+        assert_eq!(warnings.len(), 1);
+    }
+}
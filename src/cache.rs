@@ -0,0 +1,571 @@
+//! A persistent cache of per-directory aggregates of Atuin's history.
+//!
+//! Aggregating the whole `history` table on every run gets slow with
+//! hundreds of thousands of rows. The cache stores visit counts per
+//! directory, host, exit status, day and duration class, plus per-session
+//! counts, and is brought up to date on each run from the rows added since
+//! the last one.
+//! [`crate::db::query_dirs`] combines it with the few rows it does not cover.
+//!
+//! Deleted rows are noticed on every run by counting the rows, and the rows
+//! marked with `deleted_at`, that the cache covers. Either count changing
+//! means cached rows were deleted, and the cache is rebuilt.
+
+use crate::db::{self, CommandFilter, FAILED};
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Bumped whenever the cache layout or the meaning of its contents changes.
+const SCHEMA_VERSION: i64 = 3;
+
+/// Visits are aggregated per day. Visit histograms treat every visit in a
+/// day as if it happened at the last one. Cached visits are always older
+/// than [`PENDING_NS`], so this only blurs the histogram edges of a week and
+/// more, by less than a day.
+const BUCKET_NS: i64 = 24 * 3_600_000_000_000;
+
+/// Rows younger than this are never cached but always read from the history
+/// table, because Atuin fills in the exit status and duration of a command
+/// only once it finishes.
+const PENDING_NS: i64 = 24 * 3_600_000_000_000;
+
+const META_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value NOT NULL
+    );
+";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS visits (
+        cwd TEXT NOT NULL,
+        hostname TEXT NOT NULL,
+        failed INTEGER NOT NULL,
+        day INTEGER NOT NULL,
+        duration_class INTEGER NOT NULL,
+        visits INTEGER NOT NULL,
+        last_visit INTEGER NOT NULL,
+        PRIMARY KEY (cwd, hostname, failed, day, duration_class)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS sessions (
+        session TEXT NOT NULL,
+        cwd TEXT NOT NULL,
+        hostname TEXT NOT NULL,
+        failed INTEGER NOT NULL,
+        visits INTEGER NOT NULL,
+        last_visit INTEGER NOT NULL,
+        PRIMARY KEY (session, cwd, hostname, failed)
+    ) WITHOUT ROWID;
+";
+
+/// Default location of the cache: `$XDG_CACHE_HOME/atuin-z/index.db`, or
+/// `~/.cache/atuin-z/index.db`.
+pub fn cache_path() -> Result<PathBuf> {
+    let base = if let Ok(xdg) = std::env::var("XDG_CACHE_HOME") {
+        PathBuf::from(xdg)
+    } else {
+        let home = dirs::home_dir().context("could not determine home directory")?;
+        home.join(".cache")
+    };
+    Ok(base.join("atuin-z").join("index.db"))
+}
+
+/// What the cache was built from. Any difference means it must be rebuilt.
+#[derive(Debug, PartialEq)]
+struct State {
+    version: i64,
+    /// The Atuin database the cache was built from.
+    db_path: String,
+    /// [`CommandFilter::fingerprint`] of the filter applied while building.
+    filter: String,
+    /// Rows of `history` up to this rowid are included.
+    watermark: i64,
+    /// Number of rows, and of deleted rows, up to the watermark. A change
+    /// means rows were deleted after they were cached.
+    rows: i64,
+    deleted: i64,
+}
+
+impl State {
+    fn read(tx: &Transaction) -> Result<Option<Self>> {
+        let get_i64 = |key: &str| -> Result<Option<i64>> {
+            Ok(tx
+                .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                    row.get(0)
+                })
+                .optional()?)
+        };
+        let get_str = |key: &str| -> Result<Option<String>> {
+            Ok(tx
+                .query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+                    row.get(0)
+                })
+                .optional()?)
+        };
+        let (
+            Some(version),
+            Some(db_path),
+            Some(filter),
+            Some(watermark),
+            Some(rows),
+            Some(deleted),
+        ) = (
+            get_i64("version")?,
+            get_str("db_path")?,
+            get_str("filter")?,
+            get_i64("watermark")?,
+            get_i64("rows")?,
+            get_i64("deleted")?,
+        )
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            version,
+            db_path,
+            filter,
+            watermark,
+            rows,
+            deleted,
+        }))
+    }
+
+    fn write(&self, tx: &Transaction) -> Result<()> {
+        let mut stmt = tx.prepare("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)")?;
+        stmt.execute(params!["version", self.version])?;
+        stmt.execute(params!["db_path", self.db_path])?;
+        stmt.execute(params!["filter", self.filter])?;
+        stmt.execute(params!["watermark", self.watermark])?;
+        stmt.execute(params!["rows", self.rows])?;
+        stmt.execute(params!["deleted", self.deleted])?;
+        Ok(())
+    }
+}
+
+/// Bring the cache at `path` up to date with the Atuin database `conn`
+/// (opened from `db_path`) and attach it to `conn` as `cache`.
+///
+/// Returns the watermark to pass as [`db::Query::cached`]. `filter` must be
+/// the filter later passed to [`db::query_dirs`].
+pub fn attach(
+    conn: &Connection,
+    path: &Path,
+    db_path: &Path,
+    filter: &CommandFilter,
+    now_ns: i64,
+) -> Result<i64> {
+    let watermark = refresh(conn, path, db_path, filter, now_ns)
+        .with_context(|| format!("failed to update cache: {}", path.display()))?;
+    conn.execute("ATTACH DATABASE ?1 AS cache", [path.to_string_lossy()])?;
+    Ok(watermark)
+}
+
+fn refresh(
+    conn: &Connection,
+    path: &Path,
+    db_path: &Path,
+    filter: &CommandFilter,
+    now_ns: i64,
+) -> Result<i64> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+    }
+    let mut cache = Connection::open(path)?;
+    cache.busy_timeout(Duration::from_secs(5))?;
+    cache.execute_batch(META_SCHEMA)?;
+
+    // Concurrent runs serialise here; the loser sees the winner's state.
+    let tx = cache.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let state = State::read(&tx)?;
+    if state.as_ref().is_some_and(|s| s.version != SCHEMA_VERSION) {
+        tx.execute_batch("DROP TABLE IF EXISTS visits; DROP TABLE IF EXISTS sessions;")?;
+    }
+    tx.execute_batch(SCHEMA)?;
+
+    let mut state = match state {
+        Some(state)
+            if state.version == SCHEMA_VERSION
+                && state.db_path == db_path.to_string_lossy()
+                && state.filter == filter.fingerprint()
+                && row_counts(conn, 0, state.watermark)? == (state.rows, state.deleted) =>
+        {
+            state
+        }
+        _ => {
+            tx.execute_batch("DELETE FROM visits; DELETE FROM sessions;")?;
+            State {
+                version: SCHEMA_VERSION,
+                db_path: db_path.to_string_lossy().into_owned(),
+                filter: filter.fingerprint(),
+                watermark: 0,
+                rows: 0,
+                deleted: 0,
+            }
+        }
+    };
+
+    let watermark = new_watermark(conn, state.watermark, now_ns - PENDING_NS)?;
+    if watermark > state.watermark {
+        add_rows(conn, &tx, filter, state.watermark, watermark)?;
+        let (rows, deleted) = row_counts(conn, state.watermark, watermark)?;
+        state.rows += rows;
+        state.deleted += deleted;
+        state.watermark = watermark;
+    }
+    state.write(&tx)?;
+    tx.commit()?;
+    Ok(state.watermark)
+}
+
+/// Number of rows and of deleted rows with `low < rowid <= high`.
+fn row_counts(conn: &Connection, low: i64, high: i64) -> Result<(i64, i64)> {
+    Ok(conn.query_row(
+        "SELECT count(*), count(deleted_at) FROM history WHERE rowid > ?1 AND rowid <= ?2",
+        [low, high],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?)
+}
+
+/// The highest rowid the cache can cover: everything after `watermark` up to
+/// the first row at or after `cutoff_ns`. Rows synced from other machines
+/// can arrive with old timestamps, so this goes by rowid, not timestamp.
+fn new_watermark(conn: &Connection, watermark: i64, cutoff_ns: i64) -> Result<i64> {
+    let first_pending: Option<i64> = conn.query_row(
+        "SELECT min(rowid) FROM history WHERE rowid > ?1 AND timestamp >= ?2",
+        [watermark, cutoff_ns],
+        |row| row.get(0),
+    )?;
+    Ok(match first_pending {
+        Some(rowid) => rowid - 1,
+        None => conn.query_row("SELECT coalesce(max(rowid), 0) FROM history", [], |row| {
+            row.get(0)
+        })?,
+    }
+    .max(watermark))
+}
+
+/// Add the visits in rows `low < rowid <= high` to the cache.
+fn add_rows(
+    conn: &Connection,
+    tx: &Transaction,
+    filter: &CommandFilter,
+    low: i64,
+    high: i64,
+) -> Result<()> {
+    let visit = db::visit_condition(conn, Some(filter))?;
+
+    let mut select = conn.prepare(&format!(
+        "SELECT cwd, hostname, {FAILED}, timestamp / {BUCKET_NS}, {}, count(*), \
+         max(timestamp) \
+         FROM history WHERE rowid > ?1 AND rowid <= ?2 AND {visit} \
+         GROUP BY 1, 2, 3, 4, 5",
+        db::duration_class()
+    ))?;
+    let mut upsert = tx.prepare(
+        "INSERT INTO visits (cwd, hostname, failed, day, duration_class, visits, last_visit) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+         ON CONFLICT DO UPDATE SET visits = visits + excluded.visits, \
+         last_visit = max(last_visit, excluded.last_visit)",
+    )?;
+    let mut rows = select.query([low, high])?;
+    while let Some(row) = rows.next()? {
+        upsert.execute(params![
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, bool>(2)?,
+            row.get::<_, i64>(3)?,
+            row.get::<_, i64>(4)?,
+            row.get::<_, i64>(5)?,
+            row.get::<_, i64>(6)?,
+        ])?;
+    }
+
+    let mut select = conn.prepare(&format!(
+        "SELECT session, cwd, hostname, {FAILED}, count(*), max(timestamp) \
+         FROM history WHERE rowid > ?1 AND rowid <= ?2 AND {visit} \
+         GROUP BY 1, 2, 3, 4"
+    ))?;
+    let mut upsert = tx.prepare(
+        "INSERT INTO sessions (session, cwd, hostname, failed, visits, last_visit) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+         ON CONFLICT DO UPDATE SET visits = visits + excluded.visits, \
+         last_visit = max(last_visit, excluded.last_visit)",
+    )?;
+    let mut rows = select.query([low, high])?;
+    while let Some(row) = rows.next()? {
+        upsert.execute(params![
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, bool>(3)?,
+            row.get::<_, i64>(4)?,
+            row.get::<_, i64>(5)?,
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{DirEntry, Query};
+
+    const HOUR: i64 = 3_600_000_000_000;
+    const DAY: i64 = BUCKET_NS;
+    const NOW: i64 = 20_000 * DAY;
+
+    /// An Atuin database file plus a cache file in a temp directory.
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        db_path: PathBuf,
+        cache_path: PathBuf,
+        writer: Connection,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let db_path = dir.path().join("history.db");
+            let cache_path = dir.path().join("cache").join("index.db");
+            let writer = Connection::open(&db_path).unwrap();
+            db::create_schema(&writer).unwrap();
+            Self {
+                _dir: dir,
+                db_path,
+                cache_path,
+                writer,
+            }
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn insert(
+            &self,
+            id: &str,
+            cwd: &str,
+            timestamp: i64,
+            exit: i64,
+            command: &str,
+            session: &str,
+            hostname: &str,
+        ) {
+            self.writer
+                .execute(
+                    "INSERT INTO history (id, timestamp, duration, exit, command, cwd, session, hostname)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![id, timestamp, timestamp % 1000, exit, command, cwd, session, hostname],
+                )
+                .unwrap();
+        }
+
+        /// Insert a deterministic mix of rows: several directories, hosts,
+        /// sessions, exit statuses and ages, each on a distinct day so that
+        /// the daily buckets do not blur the histograms.
+        fn insert_mix(&self, prefix: &str, count: i64, newest: i64) {
+            for i in 0..count {
+                let cwd = format!("/{}", ["a", "a/b", "c", "d/e/f"][(i % 4) as usize]);
+                let host = ["laptop:kim", "server:kim", "laptop:root"][(i % 3) as usize];
+                let session = ["s1", "s2", "s3", "s4", "s5"][(i % 5) as usize];
+                let exit = [0, 0, 1, 127, -1][(i % 5) as usize];
+                let command = ["cargo build", "ls", "git status", "make"][(i % 7 % 4) as usize];
+                let timestamp = newest - i * DAY - 1;
+                self.insert(
+                    &format!("{prefix}{i}"),
+                    &cwd,
+                    timestamp,
+                    exit,
+                    command,
+                    session,
+                    host,
+                );
+            }
+        }
+
+        fn query(&self, query: &Query, cached: bool) -> Vec<DirEntry> {
+            let conn = db::open(&self.db_path).unwrap();
+            let empty = CommandFilter::new(&[], &[]).unwrap();
+            let filter = query.commands.unwrap_or(&empty);
+            let watermark = cached
+                .then(|| attach(&conn, &self.cache_path, &self.db_path, filter, NOW).unwrap());
+            let mut entries = db::query_dirs(
+                &conn,
+                &Query {
+                    cached: watermark,
+                    commands: query.commands,
+                    ..*query
+                },
+            )
+            .unwrap();
+            entries.sort_by(|a, b| a.cwd.cmp(&b.cwd));
+            entries
+        }
+
+        fn assert_cache_matches(&self, query: &Query) {
+            let live = self.query(query, false);
+            assert!(!live.is_empty());
+            assert_eq!(self.query(query, true), live);
+        }
+
+        fn state(&self) -> State {
+            let mut cache = Connection::open(&self.cache_path).unwrap();
+            let tx = cache.transaction().unwrap();
+            State::read(&tx).unwrap().unwrap()
+        }
+    }
+
+    fn queries(filter: &CommandFilter) -> Vec<Query<'_>> {
+        vec![
+            Query::new(NOW),
+            Query {
+                local_host: Some("laptop"),
+                host: Some("laptop"),
+                ..Query::new(NOW)
+            },
+            Query {
+                session: Some("s2"),
+                active_since_ns: Some(NOW - 30 * DAY),
+                cwd_prefix: Some("/d"),
+                ..Query::new(NOW)
+            },
+            Query {
+                successful_only: true,
+                host: Some("laptop:root"),
+                session: Some("s1"),
+                ..Query::new(NOW)
+            },
+            Query {
+                commands: Some(filter),
+                ..Query::new(NOW)
+            },
+        ]
+    }
+
+    #[test]
+    fn cached_results_match_live_results() {
+        let fixture = Fixture::new();
+        fixture.insert_mix("old", 200, NOW - 2 * DAY);
+        fixture.insert_mix("new", 5, NOW);
+        let filter = CommandFilter::new(&[], &["^ls$".to_string()]).unwrap();
+        for query in queries(&filter) {
+            fixture.assert_cache_matches(&query);
+        }
+    }
+
+    #[test]
+    fn refresh_adds_only_new_rows() {
+        let fixture = Fixture::new();
+        fixture.insert_mix("a", 50, NOW - 30 * DAY);
+        fixture.assert_cache_matches(&Query::new(NOW));
+        let first = fixture.state();
+        assert_eq!(first.rows, 50);
+
+        // Synced from another machine: new rowids with old timestamps.
+        fixture.insert_mix("b", 50, NOW - 60 * DAY);
+        // Recent rows stay out of the cache.
+        fixture.insert_mix("c", 3, NOW);
+        fixture.assert_cache_matches(&Query::new(NOW));
+        let second = fixture.state();
+        assert_eq!(second.rows, 100);
+        assert!(second.watermark > first.watermark);
+    }
+
+    #[test]
+    fn recent_rows_are_read_live() {
+        let fixture = Fixture::new();
+        fixture.insert("1", "/a", NOW - HOUR, -1, "cargo test", "s1", "laptop:kim");
+        fixture.query(&Query::new(NOW), true);
+        assert_eq!(fixture.state().watermark, 0);
+
+        // The command finishes and Atuin records its exit status.
+        fixture
+            .writer
+            .execute("UPDATE history SET exit = 1", [])
+            .unwrap();
+        let entries = fixture.query(&Query::new(NOW), true);
+        assert_eq!(entries[0].failed_freq, 1);
+    }
+
+    #[test]
+    fn deletions_trigger_a_rebuild() {
+        let fixture = Fixture::new();
+        fixture.insert_mix("a", 40, NOW - 2 * DAY);
+        fixture.assert_cache_matches(&Query::new(NOW));
+
+        // Rows removed from the table are noticed right away.
+        fixture
+            .writer
+            .execute("DELETE FROM history WHERE id = 'a7'", [])
+            .unwrap();
+        fixture.assert_cache_matches(&Query::new(NOW));
+        assert_eq!(fixture.state().rows, 39);
+    }
+
+    #[test]
+    fn soft_deletions_trigger_a_rebuild() {
+        let fixture = Fixture::new();
+        fixture.insert_mix("a", 40, NOW - 2 * DAY);
+        fixture.assert_cache_matches(&Query::new(NOW));
+
+        // Atuin marks a deleted row and overwrites its command.
+        fixture
+            .writer
+            .execute(
+                "UPDATE history SET deleted_at = 1, command = 'x' WHERE id = 'a3'",
+                [],
+            )
+            .unwrap();
+        fixture.assert_cache_matches(&Query::new(NOW));
+        assert_eq!(fixture.state().deleted, 1);
+    }
+
+    #[test]
+    fn old_schema_is_replaced() {
+        let fixture = Fixture::new();
+        fixture.insert_mix("a", 40, NOW - 2 * DAY);
+        fixture.query(&Query::new(NOW), true);
+
+        let cache = Connection::open(&fixture.cache_path).unwrap();
+        cache
+            .execute_batch(
+                "DROP TABLE visits;
+                 CREATE TABLE visits (cwd, hostname, failed, hour, visits, duration, last_visit);
+                 UPDATE meta SET value = 1 WHERE key = 'version';",
+            )
+            .unwrap();
+        drop(cache);
+        fixture.assert_cache_matches(&Query::new(NOW));
+        assert_eq!(fixture.state().version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn filter_change_triggers_a_rebuild() {
+        let fixture = Fixture::new();
+        fixture.insert_mix("a", 40, NOW - 2 * DAY);
+        fixture.assert_cache_matches(&Query::new(NOW));
+
+        let filter = CommandFilter::new(&["^cargo ".to_string()], &[]).unwrap();
+        fixture.assert_cache_matches(&Query {
+            commands: Some(&filter),
+            ..Query::new(NOW)
+        });
+        assert_eq!(fixture.state().filter, filter.fingerprint());
+        fixture.assert_cache_matches(&Query::new(NOW));
+    }
+
+    #[test]
+    fn other_database_triggers_a_rebuild() {
+        let fixture = Fixture::new();
+        fixture.insert_mix("a", 40, NOW - 2 * DAY);
+        fixture.query(&Query::new(NOW), true);
+
+        let other = Fixture::new();
+        other.insert_mix("b", 10, NOW - 2 * DAY);
+        std::fs::create_dir_all(other.cache_path.parent().unwrap()).unwrap();
+        std::fs::copy(&fixture.cache_path, &other.cache_path).unwrap();
+        other.assert_cache_matches(&Query::new(NOW));
+        assert_eq!(other.state().rows, 10);
+    }
+}
@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "atuin-z", about = "Frecency-based directory jumping from Atuin history")]
@@ -7,6 +8,18 @@ pub struct Cli {
     #[arg(short, long)]
     pub list: bool,
 
+    /// Output format for --list
+    #[arg(long, value_enum, default_value_t = Format::Plain, requires = "list")]
+    pub format: Format,
+
+    /// End each output record with NUL instead of a newline
+    #[arg(short = '0', long)]
+    pub null: bool,
+
+    /// Choose among the matches in an interactive picker
+    #[arg(short, long)]
+    pub interactive: bool,
+
     /// Rank by frequency only
     #[arg(short, long)]
     pub rank: bool,
@@ -15,14 +28,44 @@ pub struct Cli {
     #[arg(short, long)]
     pub time: bool,
 
+    /// Rank by frequency with smooth exponential decay
+    #[arg(short, long)]
+    pub decay: bool,
+
+    /// Rank by the sum of per-visit weights, so old bursts of activity fade
+    #[arg(short, long)]
+    pub per_visit: bool,
+
+    /// Half-life used by --decay (e.g. 12h, 3d, 2w) [default: 3d]
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub half_life: Option<Duration>,
+
     /// Restrict to subdirectories of $ATUIN_Z_PWD
     #[arg(short, long)]
     pub current: bool,
 
-    /// Add a path to the exclusion list
+    /// Only use history from this host: a name, host:user, `current` or
+    /// `all` [default: all]
+    #[arg(long, value_name = "HOST")]
+    pub host: Option<String>,
+
+    /// Add a path to the exclusion list (defaults to $ATUIN_Z_PWD)
     #[arg(short = 'x', long)]
     pub exclude: bool,
 
+    /// With -x, also exclude everything beneath the path
+    #[arg(long, requires = "exclude")]
+    pub recursive: bool,
+
+    /// With -x, only exclude for this long (e.g. 3d, 2w)
+    #[arg(
+        long = "for",
+        value_name = "DURATION",
+        requires = "exclude",
+        value_parser = humantime::parse_duration
+    )]
+    pub exclude_for: Option<Duration>,
+
     /// Override database path
     #[arg(long)]
     pub db: Option<String>,
@@ -40,7 +83,48 @@ pub enum Command {
     Init {
         /// Shell type
         shell: Shell,
+        /// Name of the generated function; the interactive variant gets an
+        /// `i` suffix
+        #[arg(long, default_value = "z")]
+        cmd: String,
+        /// Also replace `cd` with one that falls back to the best-ranked
+        /// match when the path does not exist
+        #[arg(long)]
+        cd: bool,
+    },
+    /// Show how the directories matching the keywords were scored, and why
+    /// any were filtered out
+    Explain {
+        /// Keywords to match against directory paths
+        keywords: Vec<String>,
+    },
+    /// Print completion candidates for the given keywords
+    #[command(hide = true)]
+    Complete {
+        /// Words typed so far, including the one being completed
+        #[arg(allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+    /// Manage the exclusion list
+    Exclusions {
+        #[command(subcommand)]
+        action: ExclusionsAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ExclusionsAction {
+    /// List exclusions and whether each directory still exists
+    List,
+    /// Remove exclusions (defaults to $ATUIN_Z_PWD)
+    Remove {
+        /// Paths or patterns, as shown by `list`
+        entries: Vec<String>,
     },
+    /// Remove all exclusions
+    Clear,
+    /// Edit the exclusion list in $VISUAL / $EDITOR
+    Edit,
 }
 
 #[derive(Clone, clap::ValueEnum)]
@@ -48,4 +132,26 @@ pub enum Shell {
     Bash,
     Zsh,
     Fish,
+    /// POSIX sh (dash, busybox ash, ...)
+    #[value(alias = "sh")]
+    Posix,
+    #[value(alias = "nu")]
+    Nushell,
+    #[value(alias = "pwsh")]
+    Powershell,
+    Elvish,
+    Xonsh,
+}
+
+/// Output format for `--list`.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Score and path, aligned for reading
+    Plain,
+    /// A single JSON array of records
+    Json,
+    /// One JSON record per line
+    Jsonl,
+    /// Tab-separated fields, with escaped paths
+    Tsv,
 }
@@ -0,0 +1,721 @@
+use crate::db::CommandFilter;
+use crate::exclusions::ExclusionSet;
+use crate::existence::DirCheck;
+use crate::frecency::{Mode, Weights, DEFAULT_WEIGHTS, FRECENCY_BUCKETS, HISTOGRAM_BUCKETS};
+use crate::matching;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Environment variable that overrides the config file location.
+const CONFIG_PATH_VAR: &str = "ATUIN_Z_CONFIG";
+
+/// Prefix of environment variables that override individual config keys.
+/// Sections and keys are separated by `__`, e.g.
+/// `ATUIN_Z_MATCHING__BASENAME_BOOST=2` sets `basename_boost` in `[matching]`.
+const OVERRIDE_PREFIX: &str = "ATUIN_Z_";
+const OVERRIDE_SEPARATOR: &str = "__";
+
+/// atuin-z configuration, loaded from `config.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub defaults: Defaults,
+    pub scoring: Scoring,
+    pub matching: Matching,
+    pub exclusions: Exclusions,
+    pub history: History,
+    pub cache: Cache,
+    /// Settings from Atuin's own config file.
+    #[serde(skip)]
+    pub atuin: crate::atuin::Settings,
+}
+
+/// Defaults for command-line flags.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Defaults {
+    /// Ranking mode used when no mode flag is given.
+    pub mode: ModeName,
+    /// Half-life used by decay mode when `--half-life` is not given.
+    pub half_life: HumanDuration,
+    /// Host filter used when `--host` is not given: a host name,
+    /// `host:user`, `current` or `all`.
+    pub host: String,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Self {
+            mode: ModeName::Frecency,
+            half_life: HumanDuration::from_nanos(crate::frecency::DEFAULT_HALF_LIFE_NS),
+            host: ALL_HOSTS.to_string(),
+        }
+    }
+}
+
+/// `--host` value that disables host filtering.
+const ALL_HOSTS: &str = "all";
+/// `--host` value that selects the current host.
+const CURRENT_HOST: &str = "current";
+
+/// Ranking mode names as written in the config file.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModeName {
+    Frecency,
+    Decay,
+    PerVisit,
+    Frequency,
+    Recency,
+}
+
+/// Weights and thresholds for the scoring modes.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scoring {
+    /// Age thresholds between the frecency buckets, e.g. `["1h", "1d", "1w"]`.
+    pub bucket_thresholds: [HumanDuration; FRECENCY_BUCKETS - 1],
+    /// Weight of each frecency bucket, newest first.
+    pub bucket_weights: [f64; FRECENCY_BUCKETS],
+    /// Per-visit weight of each histogram bucket in per-visit mode.
+    pub visit_weights: [f64; HISTOGRAM_BUCKETS],
+    /// Weight of a command run on the current host relative to one run
+    /// elsewhere; `1.0` disables the boost.
+    pub host_boost: f64,
+    /// Score multiplier for directories used in the current session;
+    /// `1.0` disables the boost.
+    pub session_boost: f64,
+    /// Also apply `session_boost` to directories used in any session that
+    /// ran a command within this long, e.g. other open terminals.
+    pub active_session_window: Option<HumanDuration>,
+    /// Weight of a failed command relative to a successful one; `0` ignores
+    /// failed commands entirely.
+    pub failed_weight: f64,
+    /// Strength of the boost for directories whose commands run long;
+    /// `0.0` disables it.
+    pub duration_weight: f64,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Self {
+            bucket_thresholds: DEFAULT_WEIGHTS
+                .bucket_thresholds_ns
+                .map(HumanDuration::from_nanos),
+            bucket_weights: DEFAULT_WEIGHTS.bucket_weights,
+            visit_weights: DEFAULT_WEIGHTS.visit_weights,
+            host_boost: DEFAULT_WEIGHTS.host_boost,
+            session_boost: DEFAULT_WEIGHTS.session_boost,
+            active_session_window: None,
+            failed_weight: DEFAULT_WEIGHTS.failed_weight,
+            duration_weight: DEFAULT_WEIGHTS.duration_weight,
+        }
+    }
+}
+
+/// Keyword matching behaviour.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Matching {
+    /// Score multiplier when the last keyword matches the basename.
+    pub basename_boost: f64,
+    /// Match keywords case-sensitively.
+    pub case_sensitive: bool,
+    /// How long to wait for one directory existence check; zero waits
+    /// indefinitely.
+    pub stat_timeout: HumanDuration,
+    /// Filesystem types whose directories are assumed to exist unchecked.
+    pub skip_fs_types: Vec<String>,
+}
+
+impl Default for Matching {
+    fn default() -> Self {
+        Self {
+            basename_boost: matching::DEFAULT_BASENAME_BOOST,
+            case_sensitive: false,
+            stat_timeout: HumanDuration(crate::existence::DEFAULT_STAT_TIMEOUT),
+            skip_fs_types: Vec::new(),
+        }
+    }
+}
+
+/// Exclusion list settings.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Exclusions {
+    /// Location of the exclusions file written by `-x`.
+    pub file: Option<PathBuf>,
+    /// Additional exclusions that always apply.
+    pub paths: Vec<String>,
+    /// Name of the marker file that excludes its directory; empty to disable.
+    pub marker: String,
+    /// Whether a marker file also excludes everything beneath its directory.
+    pub marker_subtree: bool,
+}
+
+impl Default for Exclusions {
+    fn default() -> Self {
+        Self {
+            file: None,
+            paths: Vec::new(),
+            marker: crate::exclusions::DEFAULT_MARKER.to_string(),
+            marker_subtree: false,
+        }
+    }
+}
+
+/// Which history rows count as visits.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct History {
+    /// If non-empty, only commands matching one of these regexes count.
+    pub include_commands: Vec<String>,
+    /// Commands matching one of these regexes never count.
+    pub exclude_commands: Vec<String>,
+}
+
+/// Persistent cache of per-directory aggregates.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    /// Whether to use the cache; when off (the default), every run
+    /// aggregates the history.
+    pub enabled: bool,
+    /// Location of the cache database.
+    pub file: Option<PathBuf>,
+}
+
+/// A duration written in human-readable form, e.g. `"90m"` or `"3d"`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HumanDuration(pub Duration);
+
+impl HumanDuration {
+    fn from_nanos(ns: i64) -> Self {
+        Self(Duration::from_nanos(ns as u64))
+    }
+
+    pub fn as_nanos(&self) -> i64 {
+        self.0.as_nanos().min(i64::MAX as u128) as i64
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        humantime::parse_duration(&s)
+            .map(HumanDuration)
+            .map_err(|e| serde::de::Error::custom(format!("invalid duration {s:?}: {e}")))
+    }
+}
+
+impl Config {
+    /// Resolve the ranking mode, given the half-life from the command line.
+    pub fn mode(&self, half_life: Option<Duration>) -> Mode {
+        match self.defaults.mode {
+            ModeName::Frecency => Mode::Frecency,
+            ModeName::Decay => Mode::Decay {
+                half_life_ns: self.half_life_ns(half_life),
+            },
+            ModeName::PerVisit => Mode::PerVisit,
+            ModeName::Frequency => Mode::Frequency,
+            ModeName::Recency => Mode::Recency,
+        }
+    }
+
+    /// The decay half-life: the command-line value if given, else the configured default.
+    pub fn half_life_ns(&self, half_life: Option<Duration>) -> i64 {
+        half_life
+            .map_or(self.defaults.half_life, HumanDuration)
+            .as_nanos()
+    }
+
+    pub fn weights(&self) -> Weights {
+        Weights {
+            bucket_thresholds_ns: self.scoring.bucket_thresholds.map(|d| d.as_nanos()),
+            bucket_weights: self.scoring.bucket_weights,
+            visit_weights: self.scoring.visit_weights,
+            host_boost: self.scoring.host_boost,
+            session_boost: self.scoring.session_boost,
+            failed_weight: self.scoring.failed_weight,
+            duration_weight: self.scoring.duration_weight,
+        }
+    }
+
+    /// Whether failed commands are left out of the history query.
+    pub fn ignore_failed(&self) -> bool {
+        self.scoring.failed_weight == 0.0
+    }
+
+    /// Start of the active-session window for a query at `now_ns`, if any.
+    pub fn active_since_ns(&self, now_ns: i64) -> Option<i64> {
+        let window = self.scoring.active_session_window?;
+        Some(now_ns.saturating_sub(window.as_nanos()))
+    }
+
+    /// The host to filter history by, given `--host` from the command line:
+    /// `None` for all hosts.
+    pub fn host(&self, cli_host: Option<&str>) -> Option<String> {
+        match cli_host.unwrap_or(&self.defaults.host) {
+            ALL_HOSTS => None,
+            CURRENT_HOST => Some(crate::db::current_host()),
+            host => Some(host.to_string()),
+        }
+    }
+
+    /// Matching options for `mode`.
+    pub fn matching_options(&self, mode: Mode) -> matching::Options {
+        matching::Options {
+            mode,
+            weights: self.weights(),
+            basename_boost: self.matching.basename_boost,
+            case_sensitive: self.matching.case_sensitive,
+        }
+    }
+
+    /// The directory existence check configured in `[matching]`.
+    pub fn dir_check(&self) -> DirCheck {
+        let timeout = Some(self.matching.stat_timeout.0).filter(|t| !t.is_zero());
+        DirCheck::new(timeout, &self.matching.skip_fs_types)
+    }
+
+    /// Compile the command filters from `[history]`.
+    /// Atuin's `history_filter` is added to the excluded commands.
+    pub fn command_filter(&self) -> Result<CommandFilter> {
+        let mut exclude = self.history.exclude_commands.clone();
+        exclude.extend(self.atuin.history_filter.iter().cloned());
+        CommandFilter::new(&self.history.include_commands, &exclude)
+    }
+
+    /// Compile the exclusions from `entries`, the config file, and Atuin's
+    /// `cwd_filter`.
+    pub fn exclusion_set(&self, entries: &[String]) -> Result<ExclusionSet> {
+        let mut all = entries.to_vec();
+        all.extend(self.exclusions.paths.iter().cloned());
+        all.extend(self.atuin_exclusions());
+        let set = ExclusionSet::new(&all)?;
+        Ok(if self.exclusions.marker.is_empty() {
+            set
+        } else {
+            set.with_marker(&self.exclusions.marker, self.exclusions.marker_subtree)
+        })
+    }
+
+    /// Atuin's `cwd_filter` regexes as exclusion entries.
+    fn atuin_exclusions(&self) -> Vec<String> {
+        self.atuin
+            .cwd_filter
+            .iter()
+            .map(|re| format!("re:{re}"))
+            .collect()
+    }
+
+    /// The cache database: the configured one if set, else the default location.
+    pub fn cache_file(&self) -> Result<PathBuf> {
+        match &self.cache.file {
+            Some(file) => expand_home(file),
+            None => crate::cache::cache_path(),
+        }
+    }
+
+    /// The exclusions file: the configured one if set, else the default location.
+    pub fn exclusions_file(&self) -> Result<PathBuf> {
+        match &self.exclusions.file {
+            Some(file) => expand_home(file),
+            None => crate::exclusions::exclusions_path(),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.defaults.half_life.0.is_zero() {
+            bail!("defaults.half_life must be greater than zero");
+        }
+
+        let thresholds = &self.scoring.bucket_thresholds;
+        if thresholds[0].0.is_zero() {
+            bail!("scoring.bucket_thresholds must be greater than zero");
+        }
+        if thresholds.windows(2).any(|w| w[0].0 >= w[1].0) {
+            bail!("scoring.bucket_thresholds must be strictly increasing");
+        }
+
+        check_weights("scoring.bucket_weights", &self.scoring.bucket_weights)?;
+        check_weights("scoring.visit_weights", &self.scoring.visit_weights)?;
+        check_weights("scoring.failed_weight", &[self.scoring.failed_weight])?;
+        check_weights("scoring.duration_weight", &[self.scoring.duration_weight])?;
+
+        if self.exclusions.marker.contains('/') {
+            bail!(
+                "exclusions.marker must be a file name, got {:?}",
+                self.exclusions.marker
+            );
+        }
+
+        check_boost("matching.basename_boost", self.matching.basename_boost)?;
+        check_boost("scoring.host_boost", self.scoring.host_boost)?;
+        check_boost("scoring.session_boost", self.scoring.session_boost)?;
+
+        self.command_filter()
+            .context("history.include_commands / history.exclude_commands")?;
+
+        if self.defaults.host.is_empty() {
+            bail!("defaults.host must be a host name, \"{CURRENT_HOST}\" or \"{ALL_HOSTS}\"");
+        }
+
+        Ok(())
+    }
+}
+
+fn check_weights(key: &str, weights: &[f64]) -> Result<()> {
+    if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w < 0.0) {
+        bail!("{key} must contain non-negative numbers, got {w}");
+    }
+    Ok(())
+}
+
+fn check_boost(key: &str, boost: f64) -> Result<()> {
+    if !boost.is_finite() || boost <= 0.0 {
+        bail!("{key} must be a positive number, got {boost}");
+    }
+    Ok(())
+}
+
+pub fn expand_home(path: &std::path::Path) -> Result<PathBuf> {
+    match path.strip_prefix("~") {
+        Ok(rest) => {
+            let home = dirs::home_dir().context("could not determine home directory")?;
+            Ok(home.join(rest))
+        }
+        Err(_) => Ok(path.to_path_buf()),
+    }
+}
+
+/// Resolve the path to the config file.
+///
+/// Priority:
+/// 1. `ATUIN_Z_CONFIG` env var
+/// 2. `XDG_CONFIG_HOME` / atuin-z / config.toml
+/// 3. ~/.config/atuin-z/config.toml
+pub fn config_path() -> Result<PathBuf> {
+    if let Ok(p) = std::env::var(CONFIG_PATH_VAR) {
+        return Ok(PathBuf::from(p));
+    }
+
+    let base = if let Ok(xdg) = std::env::var("XDG_CONFIG_HOME") {
+        PathBuf::from(xdg)
+    } else {
+        let home = dirs::home_dir().context("could not determine home directory")?;
+        home.join(".config")
+    };
+    Ok(base.join("atuin-z").join("config.toml"))
+}
+
+/// Load the config file, if any, and apply `ATUIN_Z_*` environment overrides.
+/// Atuin's own config file is loaded as well.
+pub fn load() -> Result<Config> {
+    let path = config_path()?;
+    let content = if path.exists() {
+        std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config file: {}", path.display()))?
+    } else {
+        String::new()
+    };
+    let mut config = parse(&content, std::env::vars())
+        .with_context(|| format!("invalid configuration in {}", path.display()))?;
+
+    config.atuin = crate::atuin::load()?;
+    config
+        .command_filter()
+        .context("invalid history_filter in Atuin config")?;
+    ExclusionSet::new(&config.atuin_exclusions()).context("invalid cwd_filter in Atuin config")?;
+    Ok(config)
+}
+
+/// Parse and validate config file contents, applying environment overrides.
+fn parse(content: &str, env: impl Iterator<Item = (String, String)>) -> Result<Config> {
+    // Deserialize the file on its own first so errors carry line numbers.
+    let mut config: Config = toml::from_str(content)?;
+
+    let mut table: toml::Table = toml::from_str(content)?;
+    let mut overridden = Vec::new();
+    for (name, value) in env {
+        if apply_override(&mut table, &name, &value)? {
+            overridden.push(name);
+        }
+    }
+    if !overridden.is_empty() {
+        config = toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("invalid environment override ({})", overridden.join(", ")))?;
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+/// Apply a single `ATUIN_Z_SECTION__KEY=value` override to `table`. Returns
+/// `false` if `name` is not an override variable.
+fn apply_override(table: &mut toml::Table, name: &str, value: &str) -> Result<bool> {
+    let Some(key_path) = name.strip_prefix(OVERRIDE_PREFIX) else {
+        return Ok(false);
+    };
+    if !key_path.contains(OVERRIDE_SEPARATOR) {
+        // Plain ATUIN_Z_* variables (ATUIN_Z_PWD, ATUIN_Z_CONFIG) are not overrides.
+        return Ok(false);
+    }
+
+    let keys: Vec<String> = key_path
+        .split(OVERRIDE_SEPARATOR)
+        .map(|k| k.to_lowercase())
+        .collect();
+    let (last, sections) = keys.split_last().expect("split yields at least one key");
+
+    let mut current = table;
+    for section in sections {
+        let entry = current
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        current = match entry {
+            toml::Value::Table(t) => t,
+            _ => bail!("{name}: `{section}` is not a config section"),
+        };
+    }
+    current.insert(last.clone(), parse_override_value(value));
+    Ok(true)
+}
+
+/// Interpret an override as a TOML value (`2`, `true`, `[1, 2]`), falling
+/// back to a plain string (`decay`, `3d`).
+fn parse_override_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("v = {value}"))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(content: &str) -> Result<Config> {
+        parse(content, std::iter::empty())
+    }
+
+    fn parse_env(content: &str, env: &[(&str, &str)]) -> Result<Config> {
+        parse(
+            content,
+            env.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+        )
+    }
+
+    #[test]
+    fn empty_config_uses_defaults() {
+        let config = parse_str("").unwrap();
+        assert_eq!(config.mode(None), Mode::Frecency);
+        assert_eq!(config.weights(), DEFAULT_WEIGHTS);
+        assert_eq!(config.matching.basename_boost, 1.5);
+        assert!(!config.matching.case_sensitive);
+        assert!(config.exclusions.paths.is_empty());
+        assert!(!config.cache.enabled);
+    }
+
+    #[test]
+    fn full_config_parses() {
+        let config = parse_str(
+            r#"
+            [defaults]
+            mode = "decay"
+            half_life = "12h"
+
+            [scoring]
+            bucket_thresholds = ["2h", "2d", "2w"]
+            bucket_weights = [8, 4, 1, 0.5]
+            visit_weights = [1, 1, 1, 1, 1, 1, 1]
+
+            [matching]
+            basename_boost = 2.0
+            case_sensitive = true
+            stat_timeout = "1s"
+            skip_fs_types = ["nfs4", "fuse.sshfs"]
+
+            [exclusions]
+            file = "/tmp/exclusions"
+            paths = ["/tmp"]
+
+            [cache]
+            enabled = true
+            file = "/tmp/index.db"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.mode(None),
+            Mode::Decay {
+                half_life_ns: 12 * 3_600_000_000_000
+            }
+        );
+        let weights = config.weights();
+        assert_eq!(weights.bucket_thresholds_ns[0], 2 * 3_600_000_000_000);
+        assert_eq!(weights.bucket_weights, [8.0, 4.0, 1.0, 0.5]);
+        assert_eq!(config.matching.basename_boost, 2.0);
+        assert!(config.matching.case_sensitive);
+        assert_eq!(
+            config.matching.stat_timeout,
+            HumanDuration(Duration::from_secs(1))
+        );
+        assert_eq!(config.matching.skip_fs_types, ["nfs4", "fuse.sshfs"]);
+        assert_eq!(
+            config.exclusions_file().unwrap(),
+            PathBuf::from("/tmp/exclusions")
+        );
+        assert_eq!(config.exclusions.paths, vec!["/tmp".to_string()]);
+        assert!(config.cache.enabled);
+        assert_eq!(config.cache_file().unwrap(), PathBuf::from("/tmp/index.db"));
+    }
+
+    #[test]
+    fn cli_half_life_overrides_config() {
+        let config = parse_str("[defaults]\nmode = \"decay\"\nhalf_life = \"1d\"").unwrap();
+        let mode = config.mode(Some(Duration::from_secs(60)));
+        assert_eq!(
+            mode,
+            Mode::Decay {
+                half_life_ns: 60_000_000_000
+            }
+        );
+    }
+
+    #[test]
+    fn cli_host_overrides_config() {
+        let config = parse_str("[defaults]\nhost = \"laptop\"").unwrap();
+        assert_eq!(config.host(None).as_deref(), Some("laptop"));
+        assert_eq!(
+            config.host(Some("server:kim")).as_deref(),
+            Some("server:kim")
+        );
+        assert_eq!(config.host(Some("all")), None);
+        assert_eq!(parse_str("").unwrap().host(None), None);
+    }
+
+    #[test]
+    fn active_session_window_is_optional() {
+        assert_eq!(parse_str("").unwrap().active_since_ns(100), None);
+        let config = parse_str("[scoring]\nactive_session_window = \"10ns\"").unwrap();
+        assert_eq!(config.active_since_ns(100), Some(90));
+    }
+
+    #[test]
+    fn zero_failed_weight_ignores_failed_commands() {
+        assert!(!parse_str("").unwrap().ignore_failed());
+        assert!(!parse_str("[scoring]\nfailed_weight = 0.5")
+            .unwrap()
+            .ignore_failed());
+        assert!(parse_str("[scoring]\nfailed_weight = 0")
+            .unwrap()
+            .ignore_failed());
+    }
+
+    #[test]
+    fn history_section_builds_command_filter() {
+        let config = parse_str(
+            "[history]\ninclude_commands = [\"^git \"]\nexclude_commands = [\"^git status\"]",
+        )
+        .unwrap();
+        let filter = config.command_filter().unwrap();
+        assert!(filter.allows("git commit"));
+        assert!(!filter.allows("git status"));
+        assert!(!filter.allows("ls"));
+        assert!(parse_str("").unwrap().command_filter().unwrap().is_empty());
+    }
+
+    #[test]
+    fn atuin_filters_are_applied() {
+        let mut config = parse_str("[history]\nexclude_commands = [\"^ls$\"]").unwrap();
+        config.atuin = crate::atuin::Settings {
+            db_path: None,
+            history_filter: vec!["^secret".to_string()],
+            cwd_filter: vec!["^/very/secret".to_string()],
+        };
+        let filter = config.command_filter().unwrap();
+        assert!(!filter.allows("ls"));
+        assert!(!filter.allows("secret-cmd"));
+        assert!(filter.allows("cargo build"));
+
+        let set = config.exclusion_set(&[]).unwrap();
+        assert!(set.is_excluded("/very/secret/area"));
+        assert!(!set.is_excluded("/home/very/secret"));
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let err = parse_str("[matching]\nbasename_bost = 2.0").unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("basename_bost"), "{msg}");
+        assert!(msg.contains("line 2"), "{msg}");
+    }
+
+    #[test]
+    fn unknown_section_is_rejected() {
+        let err = parse_str("[scoreing]").unwrap_err();
+        assert!(format!("{err:#}").contains("scoreing"));
+    }
+
+    #[test]
+    fn invalid_mode_is_rejected() {
+        assert!(parse_str("[defaults]\nmode = \"fastest\"").is_err());
+    }
+
+    #[test]
+    fn invalid_duration_is_rejected() {
+        let err = parse_str("[defaults]\nhalf_life = \"soon\"").unwrap_err();
+        assert!(format!("{err:#}").contains("invalid duration"));
+    }
+
+    #[test]
+    fn wrong_weight_count_is_rejected() {
+        assert!(parse_str("[scoring]\nbucket_weights = [1, 2]").is_err());
+    }
+
+    #[test]
+    fn validation_rejects_bad_values() {
+        assert!(parse_str("[defaults]\nhalf_life = \"0s\"").is_err());
+        assert!(parse_str("[scoring]\nbucket_thresholds = [\"1d\", \"1h\", \"1w\"]").is_err());
+        assert!(parse_str("[scoring]\nbucket_weights = [4, 2, -1, 0]").is_err());
+        assert!(parse_str("[matching]\nbasename_boost = 0").is_err());
+        assert!(parse_str("[scoring]\nhost_boost = -1").is_err());
+        assert!(parse_str("[scoring]\nfailed_weight = -0.5").is_err());
+        assert!(parse_str("[scoring]\nduration_weight = -1").is_err());
+        assert!(parse_str("[history]\nexclude_commands = [\"(\"]").is_err());
+        assert!(parse_str("[defaults]\nhost = \"\"").is_err());
+        assert!(parse_str("[exclusions]\nmarker = \"a/b\"").is_err());
+    }
+
+    #[test]
+    fn env_overrides_file_values() {
+        let config = parse_env(
+            "[matching]\nbasename_boost = 2.0",
+            &[
+                ("ATUIN_Z_MATCHING__BASENAME_BOOST", "3"),
+                ("ATUIN_Z_DEFAULTS__MODE", "per-visit"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.matching.basename_boost, 3.0);
+        assert_eq!(config.mode(None), Mode::PerVisit);
+    }
+
+    #[test]
+    fn env_override_with_unknown_key_is_rejected() {
+        let err = parse_env("", &[("ATUIN_Z_MATCHING__NOPE", "1")]).unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("ATUIN_Z_MATCHING__NOPE"), "{msg}");
+        assert!(msg.contains("nope"), "{msg}");
+    }
+
+    #[test]
+    fn plain_atuin_z_vars_are_not_overrides() {
+        let config = parse_env("", &[("ATUIN_Z_PWD", "/home"), ("ATUIN_Z_CONFIG", "x")]).unwrap();
+        assert_eq!(config.mode(None), Mode::Frecency);
+    }
+}
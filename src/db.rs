@@ -1,14 +1,155 @@
+use crate::frecency::{DURATION_CLASSES, DURATION_EDGES_NS, HISTOGRAM_BUCKETS, HISTOGRAM_EDGES_NS};
 use anyhow::{Context, Result};
+use regex::RegexSet;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ToSql;
 use rusqlite::{Connection, OpenFlags};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// A row from the aggregated history query.
+#[derive(Clone, Debug, PartialEq)]
 pub struct DirEntry {
     pub cwd: String,
     /// Number of commands run in this directory.
     pub freq: i64,
     /// Most recent visit timestamp in nanoseconds since Unix epoch.
     pub last_visit_ns: i64,
+    /// Number of those commands run on [`Query::local_host`].
+    pub local_freq: i64,
+    /// Number of those commands run in [`Query::session`] or in another
+    /// session active since [`Query::active_since_ns`].
+    pub session_freq: i64,
+    /// Number of those commands that failed. See [`FAILED`].
+    pub failed_freq: i64,
+    /// Number of those commands per duration class. See
+    /// [`DURATION_EDGES_NS`] for the class bounds. Commands whose duration
+    /// Atuin did not record (`-1`) count as instant.
+    pub durations: [i64; DURATION_CLASSES],
+    /// Number of visits per age bucket, relative to the `now_ns` passed to
+    /// [`query_dirs`]. See [`HISTOGRAM_EDGES_NS`] for the bucket bounds.
+    pub histogram: [i64; HISTOGRAM_BUCKETS],
+}
+
+/// What [`query_dirs`] selects and aggregates.
+pub struct Query<'a> {
+    /// Restrict results to subdirectories of this path.
+    pub cwd_prefix: Option<&'a str>,
+    /// Only count commands run on this host. See [`host_matches`].
+    pub host: Option<&'a str>,
+    /// Host whose commands are counted in [`DirEntry::local_freq`].
+    pub local_host: Option<&'a str>,
+    /// Session whose commands are counted in [`DirEntry::session_freq`],
+    /// normally `$ATUIN_SESSION`.
+    pub session: Option<&'a str>,
+    /// Also count commands from every session that ran a command at or
+    /// after this time in [`DirEntry::session_freq`].
+    pub active_since_ns: Option<i64>,
+    /// Skip failed commands entirely instead of counting them in
+    /// [`DirEntry::failed_freq`].
+    pub successful_only: bool,
+    /// Only count commands that pass this filter.
+    pub commands: Option<&'a CommandFilter>,
+    /// Skip directories whose path does not contain every keyword. This is
+    /// a prefilter: it keeps at least the directories the matcher in
+    /// [`crate::matching`] accepts, and possibly a few more.
+    pub keywords: &'a [String],
+    /// Match `keywords` case-sensitively.
+    pub case_sensitive: bool,
+    /// Read history rows up to this rowid from the cache attached as
+    /// `cache` instead of the history table. See [`crate::cache`].
+    pub cached: Option<i64>,
+    /// Reference time for the visit histograms.
+    pub now_ns: i64,
+}
+
+impl Query<'_> {
+    /// A query for every directory, with histograms relative to `now_ns`.
+    pub fn new(now_ns: i64) -> Self {
+        Self {
+            cwd_prefix: None,
+            host: None,
+            local_host: None,
+            session: None,
+            active_since_ns: None,
+            successful_only: false,
+            commands: None,
+            keywords: &[],
+            case_sensitive: false,
+            cached: None,
+            now_ns,
+        }
+    }
+}
+
+/// Regexes selecting which commands count as visits.
+///
+/// Patterns follow Atuin's `history_filter`: Rust regex syntax, matched
+/// anywhere in the command unless anchored.
+#[derive(Clone)]
+pub struct CommandFilter {
+    /// If set, only commands matching one of these count.
+    include: Option<RegexSet>,
+    /// Commands matching one of these never count.
+    exclude: RegexSet,
+}
+
+impl CommandFilter {
+    /// Compile a filter. An empty `include` list allows every command.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(RegexSet::new(include).context("invalid command pattern")?)
+        };
+        let exclude = RegexSet::new(exclude).context("invalid command pattern")?;
+        Ok(Self { include, exclude })
+    }
+
+    /// Whether the filter lets every command through.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+
+    pub fn allows(&self, command: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|set| set.is_match(command))
+            && !self.exclude.is_match(command)
+    }
+
+    /// The patterns, for telling whether two filters are the same.
+    pub fn fingerprint(&self) -> String {
+        let include = self.include.as_ref().map(|set| set.patterns());
+        format!("{:?} {:?}", include, self.exclude.patterns())
+    }
+
+    /// Register the filter as the SQL function `atuin_z_command_allowed`, so
+    /// rows are dropped before they are aggregated.
+    fn register(&self, conn: &Connection) -> Result<()> {
+        let filter = self.clone();
+        conn.create_scalar_function(
+            "atuin_z_command_allowed",
+            1,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            move |ctx| Ok(filter.allows(ctx.get_raw(0).as_str().unwrap_or(""))),
+        )?;
+        Ok(())
+    }
+}
+
+/// The current machine's host name, as Atuin records it in the first half of
+/// the `hostname` column. `ATUIN_HOST_NAME` overrides it, as in Atuin.
+pub fn current_host() -> String {
+    std::env::var("ATUIN_HOST_NAME").unwrap_or_else(|_| {
+        whoami::fallible::hostname().unwrap_or_else(|_| "localhost".to_string())
+    })
+}
+
+/// SQL condition matching rows whose `hostname` column (`host:user`) is for
+/// the host bound to parameter `?n`. A name containing `:` must match the
+/// whole column, so `host:user` selects a single user on that host.
+fn host_matches(n: usize) -> String {
+    format!("(hostname = ?{n} OR substr(hostname, 1, length(?{n}) + 1) = ?{n} || ':')")
 }
 
 /// Resolve the path to the Atuin history database.
@@ -16,10 +157,15 @@ pub struct DirEntry {
 /// Priority:
 /// 1. Explicit `--db` flag
 /// 2. `ATUIN_DB_PATH` env var
-/// 3. `ATUIN_DATA_DIR` / history.db
-/// 4. `XDG_DATA_HOME` / atuin / history.db
-/// 5. ~/.local/share/atuin/history.db
-pub fn resolve_db_path(cli_override: Option<&str>) -> Result<PathBuf> {
+/// 3. `db_path` in Atuin's config file, or `history.db` in its `data_dir`,
+///    passed as `atuin_db_path`
+/// 4. `ATUIN_DATA_DIR` / history.db
+/// 5. `XDG_DATA_HOME` / atuin / history.db
+/// 6. ~/.local/share/atuin/history.db
+pub fn resolve_db_path(
+    cli_override: Option<&str>,
+    atuin_db_path: Option<&Path>,
+) -> Result<PathBuf> {
     if let Some(p) = cli_override {
         return Ok(PathBuf::from(p));
     }
@@ -28,6 +174,10 @@ pub fn resolve_db_path(cli_override: Option<&str>) -> Result<PathBuf> {
         return Ok(PathBuf::from(p));
     }
 
+    if let Some(p) = atuin_db_path {
+        return Ok(p.to_path_buf());
+    }
+
     if let Ok(data_dir) = std::env::var("ATUIN_DATA_DIR") {
         return Ok(PathBuf::from(data_dir).join("history.db"));
     }
@@ -51,7 +201,8 @@ pub fn open(path: &PathBuf) -> Result<Connection> {
     Ok(conn)
 }
 
-/// Create the Atuin history table schema in the given connection.
+/// Create the Atuin history table schema, with Atuin's indexes, in the given
+/// connection.
 ///
 /// This is used by tests to set up an in-memory database. It is not used
 /// in production (where we read the real Atuin database).
@@ -68,44 +219,203 @@ pub fn create_schema(conn: &Connection) -> Result<()> {
             session TEXT NOT NULL,
             hostname TEXT NOT NULL,
             deleted_at INTEGER
-        );",
+        );
+        CREATE INDEX IF NOT EXISTS idx_history_ts ON history(timestamp);
+        CREATE INDEX IF NOT EXISTS idx_history_command ON history(command);
+        CREATE INDEX IF NOT EXISTS idx_history_command_timestamp ON history(command, timestamp);",
     )?;
     Ok(())
 }
 
+/// SQL condition for a failed command. Atuin records `-1` when the exit
+/// status is unknown (imported history, commands still running), so only
+/// positive statuses count as failures.
+pub const FAILED: &str = "exit > 0";
+
+/// Index of the first histogram column in the [`query_dirs`] result.
+const FIRST_HISTOGRAM_COLUMN: usize = 6;
+
+/// Index of the first duration class column in the [`query_dirs`] result.
+const FIRST_DURATION_COLUMN: usize = FIRST_HISTOGRAM_COLUMN + HISTOGRAM_EDGES_NS.len();
+
+/// SQL expression for the duration class of a history row. See
+/// [`DURATION_EDGES_NS`].
+pub fn duration_class() -> String {
+    let mut sql = "CASE".to_string();
+    for (class, edge) in DURATION_EDGES_NS.iter().enumerate() {
+        sql.push_str(&format!(" WHEN duration < {edge} THEN {class}"));
+    }
+    sql.push_str(&format!(" ELSE {} END", DURATION_EDGES_NS.len()));
+    sql
+}
+
+/// SQL condition selecting the history rows that count as visits: rows that
+/// are not deleted and, if given, pass `filter`. Registers the SQL function
+/// the filter needs on `conn`.
+pub fn visit_condition(conn: &Connection, filter: Option<&CommandFilter>) -> Result<String> {
+    let mut condition = "deleted_at IS NULL".to_string();
+    if let Some(filter) = filter.filter(|f| !f.is_empty()) {
+        filter.register(conn)?;
+        condition.push_str(" AND atuin_z_command_allowed(command)");
+    }
+    Ok(condition)
+}
+
+/// SQL condition for a session that ran a command at or after the time in
+/// parameter `param`. Only recent rows are read, via Atuin's index on
+/// `timestamp`.
+fn active_sessions(param: usize) -> String {
+    format!(
+        "session IN (SELECT session FROM main.history \
+         WHERE timestamp >= ?{param} AND deleted_at IS NULL)"
+    )
+}
+
 /// Query the history table, returning aggregated directory entries.
 ///
-/// If `cwd_prefix` is `Some`, restricts results to subdirectories of that path.
-pub fn query_dirs(conn: &Connection, cwd_prefix: Option<&str>) -> Result<Vec<DirEntry>> {
-    let (sql, params): (String, Vec<Box<dyn rusqlite::types::ToSql>>) = match cwd_prefix {
-        Some(prefix) => {
-            let pattern = format!("{}/%", prefix);
-            (
-                "SELECT cwd, count(*) AS freq, max(timestamp) AS last_visit \
-                 FROM history \
-                 WHERE deleted_at IS NULL AND cwd LIKE ?1 \
-                 GROUP BY cwd"
-                    .to_string(),
-                vec![Box::new(pattern) as Box<dyn rusqlite::types::ToSql>],
+/// See [`Query`] for the available filters. Visit histograms are bucketed by
+/// age relative to `query.now_ns`.
+pub fn query_dirs(conn: &Connection, query: &Query) -> Result<Vec<DirEntry>> {
+    let mut params: Vec<Box<dyn ToSql>> = vec![Box::new(query.now_ns)];
+
+    let mut sessions = Vec::new();
+    if let Some(session) = query.session {
+        params.push(Box::new(session.to_string()));
+        sessions.push(format!("session = ?{}", params.len()));
+    }
+    if let Some(since) = query.active_since_ns {
+        params.push(Box::new(since));
+        sessions.push(active_sessions(params.len()));
+    }
+    let in_session = (!sessions.is_empty()).then(|| format!("({})", sessions.join(" OR ")));
+
+    // One row per visit, with the same columns as the cache tables so that
+    // cached aggregates and live rows can be combined below.
+    let visit = visit_condition(conn, query.commands)?;
+    let live = format!(
+        "SELECT cwd, hostname, {FAILED} AS failed, 1 AS visits, \
+         {} AS duration_class, timestamp AS last_visit, \
+         {} AS session_visits \
+         FROM main.history WHERE {visit}",
+        duration_class(),
+        in_session.as_deref().unwrap_or("0")
+    );
+    let rows = match query.cached {
+        None => live,
+        Some(watermark) => {
+            params.push(Box::new(watermark));
+            let mut rows = "SELECT cwd, hostname, failed, visits, duration_class, last_visit, 0 \
+                            FROM cache.visits"
+                .to_string();
+            if let Some(in_session) = &in_session {
+                rows.push_str(&format!(
+                    " UNION ALL SELECT cwd, hostname, failed, 0, 0, NULL, visits \
+                     FROM cache.sessions WHERE {in_session}"
+                ));
+            }
+            rows.push_str(&format!(" UNION ALL {live} AND rowid > ?{}", params.len()));
+            rows
+        }
+    };
+
+    let local_freq = match query.local_host {
+        Some(host) => {
+            params.push(Box::new(host.to_string()));
+            format!(
+                "sum(CASE WHEN {} THEN visits ELSE 0 END)",
+                host_matches(params.len())
             )
         }
-        None => (
-            "SELECT cwd, count(*) AS freq, max(timestamp) AS last_visit \
-             FROM history \
-             WHERE deleted_at IS NULL \
-             GROUP BY cwd"
-                .to_string(),
-            vec![],
-        ),
+        None => "0".to_string(),
     };
 
+    // Cumulative count of visits younger than each edge; differenced below.
+    let histogram_columns: String = HISTOGRAM_EDGES_NS
+        .iter()
+        .map(|edge| format!(", sum(CASE WHEN ?1 - last_visit < {edge} THEN visits ELSE 0 END)"))
+        .collect();
+    // Likewise, the count of visits shorter than each duration class.
+    let duration_columns: String = (1..DURATION_CLASSES)
+        .map(|class| format!(", sum(CASE WHEN duration_class < {class} THEN visits ELSE 0 END)"))
+        .collect();
+
+    let mut sql = format!(
+        "WITH rows (cwd, hostname, failed, visits, duration_class, last_visit, session_visits) \
+         AS ({rows}) \
+         SELECT cwd, sum(visits) AS freq, max(last_visit) AS last_visit, \
+         {local_freq} AS local_freq, sum(session_visits) AS session_freq, \
+         sum(CASE WHEN failed THEN visits ELSE 0 END) AS failed_freq\
+         {histogram_columns}{duration_columns} \
+         FROM rows \
+         WHERE 1"
+    );
+
+    if let Some(prefix) = query.cwd_prefix {
+        params.push(Box::new(format!("{}/%", prefix)));
+        sql.push_str(&format!(" AND cwd LIKE ?{}", params.len()));
+    }
+
+    if let Some(host) = query.host {
+        params.push(Box::new(host.to_string()));
+        sql.push_str(&format!(" AND {}", host_matches(params.len())));
+    }
+
+    if query.successful_only {
+        sql.push_str(" AND NOT failed");
+    }
+
+    for keyword in query.keywords {
+        if query.case_sensitive {
+            params.push(Box::new(keyword.clone()));
+            sql.push_str(&format!(" AND instr(cwd, ?{}) > 0", params.len()));
+        } else {
+            // SQLite's lower() folds only ASCII letters, so paths with other
+            // characters are left for the matcher to decide.
+            params.push(Box::new(keyword.to_lowercase()));
+            sql.push_str(&format!(
+                " AND (instr(lower(cwd), ?{}) > 0 OR cwd GLOB '*[^ -~]*')",
+                params.len()
+            ));
+        }
+    }
+
+    sql.push_str(" GROUP BY cwd HAVING freq > 0");
+
     let mut stmt = conn.prepare(&sql)?;
-    let param_refs: Vec<&dyn rusqlite::types::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+    let param_refs: Vec<&dyn ToSql> = params.iter().map(|p| p.as_ref()).collect();
     let rows = stmt.query_map(param_refs.as_slice(), |row| {
+        let freq: i64 = row.get(1)?;
+        let mut histogram = [0; HISTOGRAM_BUCKETS];
+        let mut younger = 0;
+        for (i, bucket) in histogram.iter_mut().enumerate() {
+            let cumulative = if i < HISTOGRAM_EDGES_NS.len() {
+                row.get(FIRST_HISTOGRAM_COLUMN + i)?
+            } else {
+                freq
+            };
+            *bucket = cumulative - younger;
+            younger = cumulative;
+        }
+        let mut durations = [0; DURATION_CLASSES];
+        let mut shorter = 0;
+        for (i, class) in durations.iter_mut().enumerate() {
+            let cumulative = if i < DURATION_EDGES_NS.len() {
+                row.get(FIRST_DURATION_COLUMN + i)?
+            } else {
+                freq
+            };
+            *class = cumulative - shorter;
+            shorter = cumulative;
+        }
         Ok(DirEntry {
             cwd: row.get(0)?,
-            freq: row.get(1)?,
+            freq,
             last_visit_ns: row.get(2)?,
+            local_freq: row.get(3)?,
+            session_freq: row.get(4)?,
+            failed_freq: row.get(5)?,
+            durations,
+            histogram,
         })
     })?;
 
@@ -127,10 +437,50 @@ mod tests {
     }
 
     fn insert_history(conn: &Connection, id: &str, cwd: &str, timestamp: i64) {
+        insert_on_host(conn, id, cwd, timestamp, "host:user");
+    }
+
+    fn insert_on_host(conn: &Connection, id: &str, cwd: &str, timestamp: i64, hostname: &str) {
         conn.execute(
             "INSERT INTO history (id, timestamp, duration, exit, command, cwd, session, hostname)
-             VALUES (?1, ?2, 0, 0, 'test', ?3, 'sess', 'host')",
-            rusqlite::params![id, timestamp, cwd],
+             VALUES (?1, ?2, 0, 0, 'test', ?3, 'sess', ?4)",
+            rusqlite::params![id, timestamp, cwd, hostname],
+        )
+        .unwrap();
+    }
+
+    fn insert_in_session(conn: &Connection, id: &str, cwd: &str, timestamp: i64, session: &str) {
+        conn.execute(
+            "INSERT INTO history (id, timestamp, duration, exit, command, cwd, session, hostname)
+             VALUES (?1, ?2, 0, 0, 'test', ?3, ?4, 'host:user')",
+            rusqlite::params![id, timestamp, cwd, session],
+        )
+        .unwrap();
+    }
+
+    fn insert_with_exit(conn: &Connection, id: &str, cwd: &str, timestamp: i64, exit: i64) {
+        conn.execute(
+            "INSERT INTO history (id, timestamp, duration, exit, command, cwd, session, hostname)
+             VALUES (?1, ?2, 0, ?4, 'test', ?3, 'sess', 'host:user')",
+            rusqlite::params![id, timestamp, cwd, exit],
+        )
+        .unwrap();
+    }
+
+    fn insert_command(conn: &Connection, id: &str, cwd: &str, timestamp: i64, command: &str) {
+        conn.execute(
+            "INSERT INTO history (id, timestamp, duration, exit, command, cwd, session, hostname)
+             VALUES (?1, ?2, 0, 0, ?4, ?3, 'sess', 'host:user')",
+            rusqlite::params![id, timestamp, cwd, command],
+        )
+        .unwrap();
+    }
+
+    fn insert_with_duration(conn: &Connection, id: &str, cwd: &str, duration: i64) {
+        conn.execute(
+            "INSERT INTO history (id, timestamp, duration, exit, command, cwd, session, hostname)
+             VALUES (?1, 0, ?3, 0, 'test', ?2, 'sess', 'host:user')",
+            rusqlite::params![id, cwd, duration],
         )
         .unwrap();
     }
@@ -148,16 +498,25 @@ mod tests {
 
     #[test]
     fn resolve_db_path_cli_override() {
-        let path = resolve_db_path(Some("/custom/path.db")).unwrap();
+        let path = resolve_db_path(Some("/custom/path.db"), Some(Path::new("/atuin.db"))).unwrap();
         assert_eq!(path, PathBuf::from("/custom/path.db"));
     }
 
+    #[test]
+    fn resolve_db_path_uses_atuin_config() {
+        if std::env::var_os("ATUIN_DB_PATH").is_some() {
+            return;
+        }
+        let path = resolve_db_path(None, Some(Path::new("/atuin.db"))).unwrap();
+        assert_eq!(path, PathBuf::from("/atuin.db"));
+    }
+
     // --- query_dirs ---
 
     #[test]
     fn query_dirs_empty_db() {
         let conn = setup_test_db();
-        let entries = query_dirs(&conn, None).unwrap();
+        let entries = query_dirs(&conn, &Query::new(0)).unwrap();
         assert!(entries.is_empty());
     }
 
@@ -169,7 +528,7 @@ mod tests {
         insert_history(&conn, "3", "/home/user/a", 300);
         insert_history(&conn, "4", "/home/user/b", 400);
 
-        let entries = query_dirs(&conn, None).unwrap();
+        let entries = query_dirs(&conn, &Query::new(0)).unwrap();
         assert_eq!(entries.len(), 2);
 
         let a = entries.iter().find(|e| e.cwd == "/home/user/a").unwrap();
@@ -187,7 +546,7 @@ mod tests {
         insert_history(&conn, "1", "/home/user/keep", 100);
         insert_deleted(&conn, "2", "/home/user/gone", 200);
 
-        let entries = query_dirs(&conn, None).unwrap();
+        let entries = query_dirs(&conn, &Query::new(0)).unwrap();
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].cwd, "/home/user/keep");
     }
@@ -199,7 +558,14 @@ mod tests {
         insert_history(&conn, "2", "/home/user/projects/bar", 200);
         insert_history(&conn, "3", "/home/user/documents/baz", 300);
 
-        let entries = query_dirs(&conn, Some("/home/user/projects")).unwrap();
+        let entries = query_dirs(
+            &conn,
+            &Query {
+                cwd_prefix: Some("/home/user/projects"),
+                ..Query::new(0)
+            },
+        )
+        .unwrap();
         assert_eq!(entries.len(), 2);
         assert!(entries.iter().all(|e| e.cwd.starts_with("/home/user/projects/")));
     }
@@ -211,8 +577,288 @@ mod tests {
         insert_history(&conn, "1", "/home/user", 100);
         insert_history(&conn, "2", "/home/user/child", 200);
 
-        let entries = query_dirs(&conn, Some("/home/user")).unwrap();
+        let entries = query_dirs(
+            &conn,
+            &Query {
+                cwd_prefix: Some("/home/user"),
+                ..Query::new(0)
+            },
+        )
+        .unwrap();
         assert_eq!(entries.len(), 1);
         assert_eq!(entries[0].cwd, "/home/user/child");
     }
+
+    #[test]
+    fn query_dirs_filters_by_keywords() {
+        let conn = setup_test_db();
+        insert_history(&conn, "1", "/home/user/Projects/foo", 100);
+        insert_history(&conn, "2", "/home/user/projects/bar", 200);
+        insert_history(&conn, "3", "/home/user/documents/foo", 300);
+        insert_history(&conn, "4", "/home/user/Über", 400);
+
+        let keywords = vec!["PROJECTS".to_string(), "f".to_string()];
+        let mut entries = query_dirs(
+            &conn,
+            &Query {
+                keywords: &keywords,
+                ..Query::new(0)
+            },
+        )
+        .unwrap();
+        entries.sort_by(|a, b| a.cwd.cmp(&b.cwd));
+        let cwds: Vec<&str> = entries.iter().map(|e| e.cwd.as_str()).collect();
+        // Non-ASCII paths are left to the matcher.
+        assert_eq!(cwds, ["/home/user/Projects/foo", "/home/user/Über"]);
+
+        let keywords = vec!["Projects".to_string()];
+        let entries = query_dirs(
+            &conn,
+            &Query {
+                keywords: &keywords,
+                case_sensitive: true,
+                ..Query::new(0)
+            },
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].cwd, "/home/user/Projects/foo");
+    }
+
+    #[test]
+    fn query_dirs_buckets_visits_by_age() {
+        const HOUR: i64 = 3_600_000_000_000;
+        const DAY: i64 = 24 * HOUR;
+        let now = 50 * 365 * DAY;
+        let conn = setup_test_db();
+        insert_history(&conn, "1", "/a", now);
+        insert_history(&conn, "2", "/a", now - HOUR + 1);
+        insert_history(&conn, "3", "/a", now - HOUR);
+        insert_history(&conn, "4", "/a", now - 3 * DAY);
+        insert_history(&conn, "5", "/a", now - 60 * DAY);
+        insert_history(&conn, "6", "/a", now - 400 * DAY);
+        insert_history(&conn, "7", "/a", now - 800 * DAY);
+
+        let entries = query_dirs(&conn, &Query::new(now)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].histogram, [2, 1, 1, 0, 1, 0, 2]);
+        assert_eq!(entries[0].histogram.iter().sum::<i64>(), entries[0].freq);
+    }
+
+    #[test]
+    fn query_dirs_future_visits_count_as_newest() {
+        let conn = setup_test_db();
+        insert_history(&conn, "1", "/a", 500);
+
+        let entries = query_dirs(&conn, &Query::new(100)).unwrap();
+        assert_eq!(entries[0].histogram[0], 1);
+    }
+
+    #[test]
+    fn query_dirs_filters_by_host() {
+        let conn = setup_test_db();
+        insert_on_host(&conn, "1", "/a", 100, "laptop:kim");
+        insert_on_host(&conn, "2", "/a", 200, "laptop:root");
+        insert_on_host(&conn, "3", "/b", 300, "server:kim");
+        insert_on_host(&conn, "4", "/c", 400, "laptop2:kim");
+
+        let query = |host| Query {
+            host: Some(host),
+            ..Query::new(0)
+        };
+        let entries = query_dirs(&conn, &query("laptop")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].cwd, "/a");
+        assert_eq!(entries[0].freq, 2);
+
+        let entries = query_dirs(&conn, &query("laptop:root")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].freq, 1);
+        assert_eq!(entries[0].last_visit_ns, 200);
+
+        assert!(query_dirs(&conn, &query("lap")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn query_dirs_counts_local_visits() {
+        let conn = setup_test_db();
+        insert_on_host(&conn, "1", "/a", 100, "laptop:kim");
+        insert_on_host(&conn, "2", "/a", 200, "server:kim");
+        insert_on_host(&conn, "3", "/a", 300, "laptop:root");
+
+        let entries = query_dirs(
+            &conn,
+            &Query {
+                local_host: Some("laptop"),
+                ..Query::new(0)
+            },
+        )
+        .unwrap();
+        assert_eq!(entries[0].freq, 3);
+        assert_eq!(entries[0].local_freq, 2);
+
+        let entries = query_dirs(&conn, &Query::new(0)).unwrap();
+        assert_eq!(entries[0].local_freq, 0);
+    }
+
+    #[test]
+    fn query_dirs_counts_session_visits() {
+        let conn = setup_test_db();
+        insert_in_session(&conn, "1", "/a", 100, "current");
+        insert_in_session(&conn, "2", "/a", 200, "old");
+        insert_in_session(&conn, "3", "/b", 300, "other");
+        insert_in_session(&conn, "4", "/c", 400, "other");
+        insert_in_session(&conn, "5", "/b", 900, "other");
+
+        let session_freq = |query: &Query| -> Vec<(String, i64)> {
+            let mut entries: Vec<_> = query_dirs(&conn, query)
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.cwd, e.session_freq))
+                .collect();
+            entries.sort();
+            entries
+        };
+        let pairs = |v: &[(&str, i64)]| -> Vec<(String, i64)> {
+            v.iter().map(|(c, n)| (c.to_string(), *n)).collect()
+        };
+
+        let current = Query {
+            session: Some("current"),
+            ..Query::new(0)
+        };
+        assert_eq!(
+            session_freq(&current),
+            pairs(&[("/a", 1), ("/b", 0), ("/c", 0)])
+        );
+
+        // "other" ran a command at 900, so every directory it visited counts.
+        let active = Query {
+            active_since_ns: Some(500),
+            ..current
+        };
+        assert_eq!(
+            session_freq(&active),
+            pairs(&[("/a", 1), ("/b", 2), ("/c", 1)])
+        );
+
+        assert_eq!(
+            session_freq(&Query::new(0)),
+            pairs(&[("/a", 0), ("/b", 0), ("/c", 0)])
+        );
+    }
+
+    #[test]
+    fn active_sessions_use_the_timestamp_index() {
+        let conn = setup_test_db();
+        let plan: Vec<String> = conn
+            .prepare(&format!(
+                "EXPLAIN QUERY PLAN SELECT cwd FROM history WHERE {}",
+                active_sessions(1)
+            ))
+            .unwrap()
+            .query_map([500], |row| row.get(3))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert!(
+            plan.iter()
+                .any(|step| step.contains("USING INDEX idx_history_ts")),
+            "{plan:?}"
+        );
+    }
+
+    #[test]
+    fn query_dirs_counts_failed_commands() {
+        let conn = setup_test_db();
+        insert_with_exit(&conn, "1", "/a", 100, 0);
+        insert_with_exit(&conn, "2", "/a", 200, 1);
+        insert_with_exit(&conn, "3", "/a", 300, 127);
+        insert_with_exit(&conn, "4", "/a", 400, -1);
+
+        let entries = query_dirs(&conn, &Query::new(0)).unwrap();
+        assert_eq!(entries[0].freq, 4);
+        assert_eq!(entries[0].failed_freq, 2);
+    }
+
+    #[test]
+    fn query_dirs_can_skip_failed_commands() {
+        let conn = setup_test_db();
+        insert_with_exit(&conn, "1", "/a", 100, 0);
+        insert_with_exit(&conn, "2", "/a", 200, 1);
+        insert_with_exit(&conn, "3", "/typo", 300, 127);
+        insert_with_exit(&conn, "4", "/imported", 400, -1);
+
+        let query = Query {
+            successful_only: true,
+            ..Query::new(0)
+        };
+        let mut entries = query_dirs(&conn, &query).unwrap();
+        entries.sort_by(|a, b| a.cwd.cmp(&b.cwd));
+        let dirs: Vec<_> = entries.iter().map(|e| e.cwd.as_str()).collect();
+        assert_eq!(dirs, ["/a", "/imported"]);
+        assert_eq!(entries[0].freq, 1);
+        assert_eq!(entries[0].last_visit_ns, 100);
+        assert_eq!(entries[0].failed_freq, 0);
+    }
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn command_filter_follows_history_filter_semantics() {
+        let filter = CommandFilter::new(&[], &strings(&["^ls( |$)", "secret"])).unwrap();
+        assert!(!filter.allows("ls"));
+        assert!(!filter.allows("ls -la"));
+        assert!(filter.allows("lsblk"));
+        // Unanchored patterns match anywhere, as in Atuin.
+        assert!(!filter.allows("export TOKEN=secret"));
+
+        let filter =
+            CommandFilter::new(&strings(&["^(git|cargo) "]), &strings(&["^git status"])).unwrap();
+        assert!(filter.allows("cargo build"));
+        assert!(!filter.allows("git status"));
+        assert!(!filter.allows("vim"));
+
+        assert!(CommandFilter::new(&[], &[]).unwrap().is_empty());
+        assert!(CommandFilter::new(&[], &strings(&["("])).is_err());
+    }
+
+    #[test]
+    fn query_dirs_applies_command_filter() {
+        let conn = setup_test_db();
+        insert_command(&conn, "1", "/a", 100, "cargo build");
+        insert_command(&conn, "2", "/a", 200, "ls");
+        insert_command(&conn, "3", "/a", 300, "clear");
+        insert_command(&conn, "4", "/b", 400, "cd ..");
+
+        let filter = CommandFilter::new(&[], &strings(&["^(ls|clear)$", "^cd "])).unwrap();
+        let query = Query {
+            commands: Some(&filter),
+            ..Query::new(0)
+        };
+        let entries = query_dirs(&conn, &query).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].cwd, "/a");
+        assert_eq!(entries[0].freq, 1);
+        assert_eq!(entries[0].last_visit_ns, 100);
+    }
+
+    #[test]
+    fn query_dirs_counts_duration_classes() {
+        let conn = setup_test_db();
+        insert_with_duration(&conn, "1", "/a", 5_000);
+        insert_with_duration(&conn, "2", "/a", -1);
+        insert_with_duration(&conn, "3", "/a", 3_000_000_000);
+        insert_with_duration(&conn, "4", "/a", 6_999_999_999);
+        insert_with_duration(&conn, "5", "/a", 3_600_000_000_000);
+
+        let entries = query_dirs(&conn, &Query::new(0)).unwrap();
+        let mut expected = [0; DURATION_CLASSES];
+        expected[0] = 2;
+        expected[2] = 2;
+        expected[DURATION_CLASSES - 1] = 1;
+        assert_eq!(entries[0].durations, expected);
+    }
 }
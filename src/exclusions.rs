@@ -1,5 +1,229 @@
-use anyhow::{Context, Result};
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::RegexSet;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Prefix for glob entries, e.g. `glob:**/node_modules/**`.
+const GLOB_PREFIX: &str = "glob:";
+/// Prefixes for regex entries, e.g. `re:^/tmp/`.
+const REGEX_PREFIXES: [&str; 2] = ["re:", "regex:"];
+/// Prefix for subtree entries, e.g. `tree:/mnt/backup`.
+const TREE_PREFIX: &str = "tree:";
+/// Prefix that forces an entry to be read as a literal path.
+const PATH_PREFIX: &str = "path:";
+
+/// A single entry of the exclusion list.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// Excludes exactly this directory.
+    Literal(String),
+    /// Excludes this directory and everything beneath it.
+    Tree(String),
+    /// Shell-style glob matched against the whole path. `*` does not cross `/`;
+    /// `**` does.
+    Glob(String),
+    /// Regular expression searched for anywhere in the path.
+    Regex(String),
+}
+
+impl Pattern {
+    /// Parse an exclusions file entry. Entries without a recognised prefix are
+    /// literal paths.
+    pub fn parse(entry: &str) -> Pattern {
+        if let Some(tree) = entry.strip_prefix(TREE_PREFIX) {
+            return Pattern::Tree(trim_trailing_slashes(tree).to_string());
+        }
+        if let Some(glob) = entry.strip_prefix(GLOB_PREFIX) {
+            return Pattern::Glob(glob.to_string());
+        }
+        for prefix in REGEX_PREFIXES {
+            if let Some(re) = entry.strip_prefix(prefix) {
+                return Pattern::Regex(re.to_string());
+            }
+        }
+        let path = entry.strip_prefix(PATH_PREFIX).unwrap_or(entry);
+        Pattern::Literal(path.to_string())
+    }
+
+    /// Format as an exclusions file entry.
+    pub fn to_entry(&self) -> String {
+        match self {
+            Pattern::Literal(path) => {
+                let is_prefixed = [GLOB_PREFIX, TREE_PREFIX, PATH_PREFIX]
+                    .iter()
+                    .chain(REGEX_PREFIXES.iter())
+                    .any(|prefix| path.starts_with(prefix));
+                if is_prefixed {
+                    format!("{PATH_PREFIX}{path}")
+                } else {
+                    path.clone()
+                }
+            }
+            Pattern::Tree(dir) => format!("{TREE_PREFIX}{}", trim_trailing_slashes(dir)),
+            Pattern::Glob(glob) => format!("{GLOB_PREFIX}{glob}"),
+            Pattern::Regex(re) => format!("{}{re}", REGEX_PREFIXES[0]),
+        }
+    }
+}
+
+/// Strip trailing slashes, keeping the root directory as `/`.
+fn trim_trailing_slashes(dir: &str) -> &str {
+    let trimmed = dir.trim_end_matches('/');
+    if trimmed.is_empty() && dir.starts_with('/') {
+        "/"
+    } else {
+        trimmed
+    }
+}
+
+/// A compiled exclusion list.
+///
+/// Literal paths are looked up in a hash set, subtrees by looking up each
+/// ancestor of the directory, and all glob and regex entries are each compiled
+/// into a single automaton, so checking a directory does not scan the list.
+#[derive(Default)]
+pub struct ExclusionSet {
+    literals: HashSet<String>,
+    trees: HashSet<String>,
+    globs: GlobSet,
+    regexes: RegexSet,
+    markers: Option<Markers>,
+}
+
+/// Default name of the marker file that opts a directory out of ranking.
+pub const DEFAULT_MARKER: &str = ".atuin-z-ignore";
+
+/// Marker-file exclusions: directories containing a marker file (or, with
+/// `subtree`, beneath a directory containing one) are excluded.
+struct Markers {
+    name: String,
+    subtree: bool,
+    /// Whether each directory checked so far contains the marker, so shared
+    /// ancestors are only checked once per run.
+    cache: RefCell<HashMap<String, bool>>,
+}
+
+impl Markers {
+    fn is_marked(&self, dir: &str, exists: &impl Fn(&str) -> bool) -> bool {
+        let dir = trim_trailing_slashes(dir);
+        if self.subtree {
+            ancestors(dir).any(|d| self.has_marker(d, exists))
+        } else {
+            self.has_marker(dir, exists)
+        }
+    }
+
+    fn has_marker(&self, dir: &str, exists: &impl Fn(&str) -> bool) -> bool {
+        if let Some(&marked) = self.cache.borrow().get(dir) {
+            return marked;
+        }
+        let marked = exists(&Path::new(dir).join(&self.name).to_string_lossy());
+        self.cache.borrow_mut().insert(dir.to_string(), marked);
+        marked
+    }
+}
+
+impl ExclusionSet {
+    /// Compile exclusion entries. Fails if a glob or regex entry is invalid.
+    pub fn new(entries: &[String]) -> Result<Self> {
+        let mut literals = HashSet::new();
+        let mut trees = HashSet::new();
+        let mut globs = GlobSetBuilder::new();
+        let mut regexes = Vec::new();
+
+        for entry in entries {
+            match Pattern::parse(entry) {
+                Pattern::Literal(path) => {
+                    literals.insert(path);
+                }
+                Pattern::Tree(dir) => {
+                    trees.insert(dir);
+                }
+                Pattern::Glob(glob) => {
+                    globs.add(compile_glob(&glob)?);
+                }
+                Pattern::Regex(re) => {
+                    regex::Regex::new(&re)
+                        .with_context(|| format!("invalid exclusion regex: {re}"))?;
+                    regexes.push(re);
+                }
+            }
+        }
+
+        Ok(Self {
+            literals,
+            trees,
+            globs: globs.build().context("failed to compile exclusion globs")?,
+            regexes: RegexSet::new(&regexes).context("failed to compile exclusion regexes")?,
+            markers: None,
+        })
+    }
+
+    /// Also exclude directories that contain a file called `name`. With
+    /// `subtree`, a marker also excludes everything beneath its directory.
+    pub fn with_marker(mut self, name: &str, subtree: bool) -> Self {
+        self.markers = Some(Markers {
+            name: name.to_string(),
+            subtree,
+            cache: RefCell::new(HashMap::new()),
+        });
+        self
+    }
+
+    /// Check if a directory is excluded by a marker file, looking for marker
+    /// files with `exists`. Unlike [`is_excluded`](Self::is_excluded) this
+    /// touches the filesystem, so it should only be called for directories
+    /// that exist, and `exists` should guard against hung mounts, as
+    /// [`DirCheck::exists`](crate::existence::DirCheck::exists) does.
+    pub fn is_marked(&self, dir: &str, exists: impl Fn(&str) -> bool) -> bool {
+        self.markers
+            .as_ref()
+            .is_some_and(|m| m.is_marked(dir, &exists))
+    }
+
+    /// Check if a directory is excluded.
+    pub fn is_excluded(&self, dir: &str) -> bool {
+        self.literals.contains(dir)
+            || self.in_excluded_tree(dir)
+            || self.globs.is_match(dir)
+            || self.regexes.is_match(dir)
+    }
+
+    /// Check `dir` and each of its ancestors against the subtree entries.
+    /// Ancestors are cut at `/` boundaries, so `tree:/a/proj` does not
+    /// exclude `/a/project`.
+    fn in_excluded_tree(&self, dir: &str) -> bool {
+        !self.trees.is_empty()
+            && ancestors(trim_trailing_slashes(dir)).any(|d| self.trees.contains(d))
+    }
+}
+
+/// `dir` followed by each of its ancestors, cut at `/` boundaries and ending
+/// with `/` for absolute paths.
+fn ancestors(dir: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(dir), |current| match current.rfind('/') {
+        Some(0) if *current != "/" => Some("/"),
+        Some(i) if i > 0 => Some(&current[..i]),
+        _ => None,
+    })
+}
+
+fn compile_glob(glob: &str) -> Result<globset::Glob> {
+    GlobBuilder::new(glob)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid exclusion glob: {glob}"))
+}
+
+/// Check that an entry is a valid exclusion, so bad patterns are rejected
+/// before they are written to the exclusions file.
+pub fn validate(entry: &str) -> Result<()> {
+    ExclusionSet::new(&[entry.to_string()]).map(|_| ())
+}
 
 /// Resolve the path to the exclusions file.
 ///
@@ -14,53 +238,348 @@ pub fn exclusions_path() -> Result<PathBuf> {
     Ok(base.join("atuin-z").join("exclusions"))
 }
 
-/// Load the exclusion list from disk. Returns an empty vec if the file doesn't exist.
-pub fn load() -> Result<Vec<String>> {
-    let path = exclusions_path()?;
+/// Separator between an entry and its expiry, e.g. `/tmp/old\tuntil=2026-01-01T00:00:00Z`.
+const EXPIRY_SEPARATOR: &str = "\tuntil=";
+
+/// A line of the exclusions file: an entry and an optional expiry time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exclusion {
+    pub entry: String,
+    /// The entry stops applying at this time ("snoozed" exclusions).
+    pub expires: Option<SystemTime>,
+}
+
+impl Exclusion {
+    fn parse(line: &str) -> Result<Exclusion> {
+        match line.split_once(EXPIRY_SEPARATOR) {
+            Some((entry, expires)) => {
+                let expires = humantime::parse_rfc3339_weak(expires.trim())
+                    .with_context(|| format!("invalid expiry time in exclusion: {line:?}"))?;
+                Ok(Exclusion {
+                    entry: entry.to_string(),
+                    expires: Some(expires),
+                })
+            }
+            None => Ok(Exclusion {
+                entry: line.to_string(),
+                expires: None,
+            }),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        match self.expires {
+            Some(expires) => format!(
+                "{}{}{}",
+                self.entry,
+                EXPIRY_SEPARATOR,
+                humantime::format_rfc3339_seconds(expires)
+            ),
+            None => self.entry.clone(),
+        }
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// The raw content of the exclusions file at `path`; empty if it doesn't exist.
+fn read_raw(path: &Path) -> Result<String> {
     if !path.exists() {
-        return Ok(Vec::new());
-    }
-    let content = std::fs::read_to_string(&path)
-        .with_context(|| format!("failed to read exclusions file: {}", path.display()))?;
-    Ok(content
-        .lines()
-        .map(|l| l.to_string())
-        .filter(|l| !l.is_empty())
+        return Ok(String::new());
+    }
+    std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read exclusions file: {}", path.display()))
+}
+
+/// The lines of an exclusions file, including expired ones, split into the
+/// valid exclusions and the lines that have a bad expiry or pattern.
+fn parse_lines(content: &str) -> (Vec<Exclusion>, Vec<(&str, anyhow::Error)>) {
+    let mut valid = Vec::new();
+    let mut invalid = Vec::new();
+    for line in content.lines().filter(|l| !l.is_empty()) {
+        let parsed = Exclusion::parse(line).and_then(|e| {
+            validate(&e.entry).with_context(|| format!("invalid exclusion: {line:?}"))?;
+            Ok(e)
+        });
+        match parsed {
+            Ok(exclusion) => valid.push(exclusion),
+            Err(err) => invalid.push((line, err)),
+        }
+    }
+    (valid, invalid)
+}
+
+/// Read every line of the exclusions file at `path`, including expired ones.
+/// Fails on the first invalid line.
+fn read(path: &Path) -> Result<Vec<Exclusion>> {
+    let content = read_raw(path)?;
+    let (valid, invalid) = parse_lines(&content);
+    match invalid.into_iter().next() {
+        Some((_, err)) => Err(err),
+        None => Ok(valid),
+    }
+}
+
+/// Load the exclusions at `path` that haven't expired. Returns an empty vec if the file
+/// doesn't exist. Invalid lines are skipped with a warning, so that a typo in the file
+/// does not break every run.
+pub fn load_exclusions(path: &Path) -> Result<Vec<Exclusion>> {
+    let now = SystemTime::now();
+    let content = read_raw(path)?;
+    let (mut exclusions, invalid) = parse_lines(&content);
+    for (_, err) in invalid {
+        eprintln!("atuin-z: warning: {err:#}; ignoring it (fix with `z exclusions edit`)");
+    }
+    exclusions.retain(|e| !e.is_expired(now));
+    Ok(exclusions)
+}
+
+/// Load the exclusion list from `path`, skipping expired entries. Returns an empty vec
+/// if the file doesn't exist.
+pub fn load(path: &Path) -> Result<Vec<String>> {
+    Ok(load_exclusions(path)?
+        .into_iter()
+        .map(|e| e.entry)
         .collect())
 }
 
-/// Add a path to the exclusion list. Creates the file and parent directories if needed.
-pub fn add(dir: &str) -> Result<()> {
-    let path = exclusions_path()?;
-    let mut entries = load()?;
+/// Add a directory or pattern to the exclusion list at `path`, optionally expiring at
+/// `expires`. Re-adding an entry replaces its expiry. Creates the file and parent
+/// directories if needed.
+pub fn add(path: &Path, dir: &str, expires: Option<SystemTime>) -> Result<()> {
+    validate(dir)?;
+    update(path, |exclusions| {
+        match exclusions.iter_mut().find(|e| e.entry == dir) {
+            Some(existing) => existing.expires = expires,
+            None => exclusions.push(Exclusion {
+                entry: dir.to_string(),
+                expires,
+            }),
+        }
+        Ok(())
+    })
+}
 
-    if entries.iter().any(|e| e == dir) {
-        // Already excluded
+/// Remove entries from the exclusion list at `path`. Entries are compared after
+/// parsing, so `tree:/a/` removes `tree:/a`; a plain path removes both the
+/// literal and the subtree exclusion of that directory. Returns the removed
+/// entries.
+pub fn remove(path: &Path, targets: &[String]) -> Result<Vec<String>> {
+    let targets: Vec<Pattern> = targets.iter().map(|t| Pattern::parse(t)).collect();
+    update(path, |exclusions| {
+        let (removed, kept): (Vec<Exclusion>, Vec<Exclusion>) =
+            std::mem::take(exclusions).into_iter().partition(|e| {
+                let pattern = Pattern::parse(&e.entry);
+                targets.iter().any(|target| match (target, &pattern) {
+                    (Pattern::Literal(dir), Pattern::Tree(tree)) => {
+                        trim_trailing_slashes(dir) == tree
+                    }
+                    _ => *target == pattern,
+                })
+            });
+        *exclusions = kept;
+        Ok(removed.into_iter().map(|e| e.entry).collect())
+    })
+}
+
+/// Remove every entry, valid or not, from the exclusion list at `path`.
+pub fn clear(path: &Path) -> Result<()> {
+    let _lock = lock(path)?;
+    if read_raw(path)?.is_empty() {
         return Ok(());
     }
+    write_atomic(path, "")
+}
 
-    entries.push(dir.to_string());
+/// Whether the directory behind an exclusion entry still exists.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Exists,
+    Missing,
+    /// Glob and regex entries don't name a single directory.
+    Pattern,
+}
 
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+/// Check the status of an exclusion entry.
+pub fn status(entry: &str) -> Status {
+    match Pattern::parse(entry) {
+        Pattern::Literal(dir) | Pattern::Tree(dir) => {
+            if Path::new(&dir).is_dir() {
+                Status::Exists
+            } else {
+                Status::Missing
+            }
+        }
+        Pattern::Glob(_) | Pattern::Regex(_) => Status::Pattern,
     }
+}
+
+/// Open the exclusion list at `path` in the user's editor (`$VISUAL`, then
+/// `$EDITOR`, then `vi`). The edited list is validated before it replaces
+/// the original; on errors the user is asked whether to edit again.
+pub fn edit(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    edit_with(path, &editor, |err| {
+        eprintln!("atuin-z: {err:#}");
+        eprint!("Edit again? [Y/n] ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).is_ok_and(|n| n > 0)
+            && !answer.trim().eq_ignore_ascii_case("n")
+    })
+}
 
-    std::fs::write(&path, entries.join("\n") + "\n")
-        .with_context(|| format!("failed to write exclusions file: {}", path.display()))?;
+/// Like [`edit`], with an explicit editor command and a callback deciding
+/// whether to edit again after a validation error.
+fn edit_with(
+    path: &Path,
+    editor: &str,
+    mut edit_again: impl FnMut(&anyhow::Error) -> bool,
+) -> Result<()> {
+    let draft = sibling(path, ".edit");
+    create_parent(&draft)?;
+    // Edit the file as it is, so that lines `load` skips can be fixed.
+    let original = read_raw(path)?;
+    std::fs::write(&draft, &original)
+        .with_context(|| format!("failed to write {}", draft.display()))?;
 
-    Ok(())
+    loop {
+        // Run through the shell so editors with arguments (`code --wait`) work.
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$1\""))
+            .arg("sh")
+            .arg(&draft)
+            .status()
+            .with_context(|| format!("failed to run editor: {editor}"))?;
+        if !status.success() {
+            bail!(
+                "editor exited with {status}; exclusions not changed (draft kept at {})",
+                draft.display()
+            );
+        }
+
+        let edited = read(&draft).and_then(|edited| {
+            let entries: Vec<String> = edited.iter().map(|e| e.entry.clone()).collect();
+            ExclusionSet::new(&entries).map(|_| edited)
+        });
+        match edited {
+            Ok(edited) => {
+                let _lock = lock(path)?;
+                // Don't silently drop entries added by another shell while
+                // the editor was open.
+                if read_raw(path)? != original {
+                    bail!(
+                        "exclusions file changed while editing; not saved (draft kept at {})",
+                        draft.display()
+                    );
+                }
+                write_atomic(path, &lines(&edited))?;
+                let _ = std::fs::remove_file(&draft);
+                return Ok(());
+            }
+            Err(err) if edit_again(&err) => continue,
+            Err(err) => {
+                return Err(err.context(format!(
+                    "exclusions not changed (draft kept at {})",
+                    draft.display()
+                )))
+            }
+        }
+    }
+}
+
+fn lines(exclusions: &[Exclusion]) -> String {
+    exclusions.iter().map(|e| e.to_line() + "\n").collect()
 }
 
-/// Check if a directory is in the exclusion list.
-pub fn is_excluded(dir: &str, exclusions: &[String]) -> bool {
-    exclusions.iter().any(|e| e == dir)
+/// Read-modify-write the exclusion list at `path`.
+///
+/// Holds an advisory lock on a sibling `.lock` file for the whole update, so
+/// concurrent `z -x` calls can't lose each other's entries, and writes the
+/// new list to a temporary file that is renamed into place, so readers never
+/// see a truncated file. `f` only sees unexpired exclusions, so expired ones
+/// are pruned whenever the file is written. Invalid lines are kept as they
+/// are. Nothing is written if `f` fails or leaves the list unchanged.
+fn update<R>(path: &Path, f: impl FnOnce(&mut Vec<Exclusion>) -> Result<R>) -> Result<R> {
+    let _lock = lock(path)?;
+
+    let now = SystemTime::now();
+    let content = read_raw(path)?;
+    let (mut original, invalid) = parse_lines(&content);
+    original.retain(|e| !e.is_expired(now));
+    let mut exclusions = original.clone();
+    let result = f(&mut exclusions)?;
+    if exclusions != original {
+        let mut content = lines(&exclusions);
+        for (line, _) in invalid {
+            content.push_str(line);
+            content.push('\n');
+        }
+        write_atomic(path, &content)?;
+    }
+    Ok(result)
+}
+
+/// Take the advisory lock on a sibling `.lock` file of `path`, held until the
+/// returned file is dropped.
+fn lock(path: &Path) -> Result<std::fs::File> {
+    create_parent(path)?;
+    let lock_path = sibling(path, ".lock");
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("failed to open lock file: {}", lock_path.display()))?;
+    lock.lock()
+        .with_context(|| format!("failed to lock {}", lock_path.display()))?;
+    Ok(lock)
+}
+
+fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let tmp = sibling(path, &format!(".{}.tmp", std::process::id()));
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    };
+    write().map_err(|err| {
+        let _ = std::fs::remove_file(&tmp);
+        anyhow::Error::new(err).context(format!(
+            "failed to write exclusions file: {}",
+            path.display()
+        ))
+    })
+}
+
+/// `path` with `suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn is_excluded(dir: &str, exclusions: &[String]) -> bool {
+        ExclusionSet::new(exclusions).unwrap().is_excluded(dir)
+    }
+
     #[test]
     fn is_excluded_matches_exact_path() {
         let exclusions = vec!["/home/user/secret".to_string()];
@@ -89,4 +608,436 @@ mod tests {
         let exclusions = vec!["/home/user/proj".to_string()];
         assert!(!is_excluded("/home/user/project", &exclusions));
     }
+
+    // --- Patterns ---
+
+    #[test]
+    fn parse_recognises_prefixes() {
+        assert_eq!(Pattern::parse("/tmp"), Pattern::Literal("/tmp".into()));
+        assert_eq!(
+            Pattern::parse("glob:/tmp/*"),
+            Pattern::Glob("/tmp/*".into())
+        );
+        assert_eq!(Pattern::parse("re:^/tmp"), Pattern::Regex("^/tmp".into()));
+        assert_eq!(
+            Pattern::parse("regex:^/tmp"),
+            Pattern::Regex("^/tmp".into())
+        );
+        assert_eq!(
+            Pattern::parse("path:glob:x"),
+            Pattern::Literal("glob:x".into())
+        );
+    }
+
+    #[test]
+    fn parse_tree_trims_trailing_slashes() {
+        assert_eq!(
+            Pattern::parse("tree:/mnt/backup/"),
+            Pattern::Tree("/mnt/backup".into())
+        );
+        assert_eq!(Pattern::parse("tree:/"), Pattern::Tree("/".into()));
+    }
+
+    #[test]
+    fn to_entry_round_trips() {
+        for pattern in [
+            Pattern::Literal("/tmp".into()),
+            Pattern::Literal("glob:weird-dir-name".into()),
+            Pattern::Tree("/mnt".into()),
+            Pattern::Glob("**/target".into()),
+            Pattern::Regex("^/x".into()),
+        ] {
+            assert_eq!(Pattern::parse(&pattern.to_entry()), pattern);
+        }
+    }
+
+    #[test]
+    fn tree_excludes_directory_and_descendants() {
+        let exclusions = vec!["tree:/home/user/scratch".to_string()];
+        assert!(is_excluded("/home/user/scratch", &exclusions));
+        assert!(is_excluded("/home/user/scratch/a", &exclusions));
+        assert!(is_excluded("/home/user/scratch/a/b/c", &exclusions));
+        assert!(!is_excluded("/home/user", &exclusions));
+    }
+
+    #[test]
+    fn tree_respects_path_boundaries() {
+        let exclusions = vec!["tree:/home/user/proj".to_string()];
+        assert!(is_excluded("/home/user/proj/src", &exclusions));
+        assert!(!is_excluded("/home/user/project", &exclusions));
+        assert!(!is_excluded("/home/user/project/src", &exclusions));
+        assert!(!is_excluded("/home/user/pro", &exclusions));
+    }
+
+    #[test]
+    fn tree_root_excludes_everything() {
+        let exclusions = vec!["tree:/".to_string()];
+        assert!(is_excluded("/", &exclusions));
+        assert!(is_excluded("/home/user", &exclusions));
+    }
+
+    #[test]
+    fn tree_ignores_trailing_slash_on_candidate() {
+        let exclusions = vec!["tree:/mnt/backup".to_string()];
+        assert!(is_excluded("/mnt/backup/", &exclusions));
+    }
+
+    #[test]
+    fn glob_star_does_not_cross_separator() {
+        let exclusions = vec!["glob:/tmp/*".to_string()];
+        assert!(is_excluded("/tmp/scratch", &exclusions));
+        assert!(!is_excluded("/tmp/scratch/deeper", &exclusions));
+        assert!(!is_excluded("/tmp", &exclusions));
+    }
+
+    #[test]
+    fn glob_double_star_matches_any_depth() {
+        let exclusions = vec!["glob:**/node_modules/**".to_string()];
+        assert!(is_excluded(
+            "/home/user/app/node_modules/left-pad",
+            &exclusions
+        ));
+        assert!(is_excluded("/node_modules/a/b/c", &exclusions));
+        assert!(!is_excluded("/home/user/app", &exclusions));
+    }
+
+    #[test]
+    fn glob_matches_every_target_directory() {
+        let exclusions = vec!["glob:**/target".to_string()];
+        assert!(is_excluded("/home/user/crate/target", &exclusions));
+        assert!(!is_excluded("/home/user/crate/target2", &exclusions));
+        assert!(!is_excluded("/home/user/crate", &exclusions));
+    }
+
+    #[test]
+    fn regex_is_searched_in_path() {
+        let exclusions = vec!["re:/\\.cache(/|$)".to_string()];
+        assert!(is_excluded("/home/user/.cache", &exclusions));
+        assert!(is_excluded("/home/user/.cache/pip", &exclusions));
+        assert!(!is_excluded("/home/user/.cached", &exclusions));
+    }
+
+    #[test]
+    fn mixed_entries() {
+        let exclusions = vec![
+            "/exact".to_string(),
+            "glob:/tmp/*".to_string(),
+            "re:^/mnt/backup".to_string(),
+        ];
+        assert!(is_excluded("/exact", &exclusions));
+        assert!(is_excluded("/tmp/x", &exclusions));
+        assert!(is_excluded("/mnt/backup-2024", &exclusions));
+        assert!(!is_excluded("/home", &exclusions));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(validate("glob:/tmp/[").is_err());
+        assert!(validate("re:(").is_err());
+        assert!(validate("/tmp/[").is_ok());
+    }
+
+    // --- File operations ---
+
+    fn temp_file() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("atuin-z").join("exclusions");
+        (dir, path)
+    }
+
+    #[test]
+    fn add_creates_file_and_skips_duplicates() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        add(&path, "/b", None).unwrap();
+        add(&path, "/a", None).unwrap();
+        assert_eq!(load(&path).unwrap(), vec!["/a", "/b"]);
+    }
+
+    #[test]
+    fn add_rejects_invalid_pattern() {
+        let (_dir, path) = temp_file();
+        assert!(add(&path, "re:(", None).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn remove_matches_parsed_entries() {
+        let (_dir, path) = temp_file();
+        for entry in ["/a", "tree:/b", "glob:**/target", "/c"] {
+            add(&path, entry, None).unwrap();
+        }
+        let removed = remove(&path, &["tree:/b/".into(), "glob:**/target".into()]).unwrap();
+        assert_eq!(removed, vec!["tree:/b", "glob:**/target"]);
+        assert_eq!(load(&path).unwrap(), vec!["/a", "/c"]);
+    }
+
+    #[test]
+    fn remove_plain_path_removes_literal_and_tree() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        add(&path, "tree:/a", None).unwrap();
+        add(&path, "tree:/ab", None).unwrap();
+        let removed = remove(&path, &["/a".into()]).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(load(&path).unwrap(), vec!["tree:/ab"]);
+    }
+
+    #[test]
+    fn remove_missing_entry_leaves_file_alone() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        assert!(remove(&path, &["/nope".into()]).unwrap().is_empty());
+        assert_eq!(load(&path).unwrap(), vec!["/a"]);
+    }
+
+    #[test]
+    fn clear_empties_list() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        clear(&path).unwrap();
+        assert!(load(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn status_of_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().to_string_lossy().to_string();
+        assert_eq!(status(&existing), Status::Exists);
+        assert_eq!(status(&format!("tree:{existing}")), Status::Exists);
+        assert_eq!(status("/definitely/not/here"), Status::Missing);
+        assert_eq!(status("glob:/tmp/*"), Status::Pattern);
+    }
+
+    #[test]
+    fn edit_replaces_list_with_valid_result() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        edit_with(&path, "printf 'glob:/tmp/*\\n' >>", |_| false).unwrap();
+        assert_eq!(load(&path).unwrap(), vec!["/a", "glob:/tmp/*"]);
+        assert!(!sibling(&path, ".edit").exists());
+    }
+
+    #[test]
+    fn edit_rejects_invalid_result() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        let mut prompts = 0;
+        let err = edit_with(&path, "printf 're:(\\n' >>", |_| {
+            prompts += 1;
+            prompts < 2
+        })
+        .unwrap_err();
+        assert_eq!(prompts, 2);
+        assert!(format!("{err:#}").contains("exclusions not changed"));
+        assert_eq!(load(&path).unwrap(), vec!["/a"]);
+    }
+
+    #[test]
+    fn edit_refuses_to_clobber_concurrent_changes() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        let editor = format!("echo /b >> \"{}\" && echo /c >>", path.display());
+        let err = edit_with(&path, &editor, |_| false).unwrap_err();
+        assert!(format!("{err:#}").contains("changed while editing"));
+        assert_eq!(load(&path).unwrap(), vec!["/a", "/b"]);
+    }
+
+    #[test]
+    fn writes_leave_no_temporary_files() {
+        let (dir, path) = temp_file();
+        add(&path, "/a", None).unwrap();
+        remove(&path, &["/a".into()]).unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(dir.path().join("atuin-z"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["exclusions", "exclusions.lock"]);
+    }
+
+    #[test]
+    fn concurrent_adds_are_not_lost() {
+        let (_dir, path) = temp_file();
+        let threads: Vec<_> = (0..32)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || add(&path, &format!("/dir/{i}"), None).unwrap())
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let mut entries = load(&path).unwrap();
+        entries.sort();
+        let mut expected: Vec<String> = (0..32).map(|i| format!("/dir/{i}")).collect();
+        expected.sort();
+        assert_eq!(entries, expected);
+    }
+
+    // --- Expiry ---
+
+    fn in_secs(secs: i64) -> SystemTime {
+        let now = SystemTime::now();
+        if secs >= 0 {
+            now + std::time::Duration::from_secs(secs as u64)
+        } else {
+            now - std::time::Duration::from_secs(secs.unsigned_abs())
+        }
+    }
+
+    #[test]
+    fn expiry_round_trips_through_file() {
+        let (_dir, path) = temp_file();
+        let expires = humantime::parse_rfc3339("2999-01-01T00:00:00Z").unwrap();
+        add(&path, "/a", Some(expires)).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "/a\tuntil=2999-01-01T00:00:00Z\n");
+        assert_eq!(load_exclusions(&path).unwrap()[0].expires, Some(expires));
+    }
+
+    #[test]
+    fn expired_entries_are_ignored() {
+        let (_dir, path) = temp_file();
+        add(&path, "/future", Some(in_secs(3600))).unwrap();
+        create_parent(&path).unwrap();
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str("/past\tuntil=2000-01-01T00:00:00Z\n");
+        std::fs::write(&path, content).unwrap();
+
+        assert_eq!(load(&path).unwrap(), vec!["/future"]);
+    }
+
+    #[test]
+    fn expired_entries_are_pruned_on_write() {
+        let (_dir, path) = temp_file();
+        create_parent(&path).unwrap();
+        std::fs::write(&path, "/past\tuntil=2000-01-01T00:00:00Z\n/kept\n").unwrap();
+
+        add(&path, "/new", None).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "/kept\n/new\n");
+    }
+
+    #[test]
+    fn re_adding_replaces_expiry() {
+        let (_dir, path) = temp_file();
+        add(&path, "/a", Some(in_secs(60))).unwrap();
+        add(&path, "/a", None).unwrap();
+        let exclusions = load_exclusions(&path).unwrap();
+        assert_eq!(exclusions.len(), 1);
+        assert_eq!(exclusions[0].expires, None);
+    }
+
+    #[test]
+    fn invalid_lines_are_skipped_and_kept() {
+        let (_dir, path) = temp_file();
+        create_parent(&path).unwrap();
+        std::fs::write(&path, "/a\tuntil=someday\nre:(\n/b\n").unwrap();
+        assert_eq!(load(&path).unwrap(), vec!["/b"]);
+
+        add(&path, "/c", None).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content, "/b\n/c\n/a\tuntil=someday\nre:(\n");
+    }
+
+    #[test]
+    fn invalid_lines_can_be_fixed_with_edit_and_clear() {
+        let (_dir, path) = temp_file();
+        create_parent(&path).unwrap();
+        std::fs::write(&path, "/a\tuntil=someday\n/b\n").unwrap();
+        edit_with(&path, "sed -i 's/someday/2999-01-01T00:00:00Z/'", |_| false).unwrap();
+        assert_eq!(load(&path).unwrap(), vec!["/a", "/b"]);
+
+        std::fs::write(&path, "/a\tuntil=someday\n").unwrap();
+        clear(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    }
+
+    // --- Markers ---
+
+    fn marked_tree() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_string_lossy().to_string();
+        for sub in ["repo/vendor/lib", "repo/src", "other"] {
+            std::fs::create_dir_all(dir.path().join(sub)).unwrap();
+        }
+        std::fs::write(dir.path().join("repo/vendor").join(DEFAULT_MARKER), "").unwrap();
+        (dir, root)
+    }
+
+    fn exists(path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    #[test]
+    fn ancestors_walks_to_root() {
+        let all: Vec<&str> = ancestors("/a/b/c").collect();
+        assert_eq!(all, vec!["/a/b/c", "/a/b", "/a", "/"]);
+        assert_eq!(ancestors("/").collect::<Vec<_>>(), vec!["/"]);
+    }
+
+    #[test]
+    fn marker_excludes_its_directory() {
+        let (_dir, root) = marked_tree();
+        let set = ExclusionSet::default().with_marker(DEFAULT_MARKER, false);
+        assert!(set.is_marked(&format!("{root}/repo/vendor"), exists));
+        assert!(!set.is_marked(&format!("{root}/repo/vendor/lib"), exists));
+        assert!(!set.is_marked(&format!("{root}/repo/src"), exists));
+    }
+
+    #[test]
+    fn marker_subtree_excludes_descendants() {
+        let (_dir, root) = marked_tree();
+        let set = ExclusionSet::default().with_marker(DEFAULT_MARKER, true);
+        assert!(set.is_marked(&format!("{root}/repo/vendor"), exists));
+        assert!(set.is_marked(&format!("{root}/repo/vendor/lib"), exists));
+        assert!(!set.is_marked(&format!("{root}/repo"), exists));
+        assert!(!set.is_marked(&format!("{root}/other"), exists));
+    }
+
+    #[test]
+    fn marker_results_are_cached() {
+        let (dir, root) = marked_tree();
+        let set = ExclusionSet::default().with_marker(DEFAULT_MARKER, true);
+        assert!(!set.is_marked(&format!("{root}/repo/src"), exists));
+        // Adding a marker later isn't noticed for directories already checked.
+        std::fs::write(dir.path().join("repo").join(DEFAULT_MARKER), "").unwrap();
+        assert!(!set.is_marked(&format!("{root}/repo/src"), exists));
+        let cache = set.markers.as_ref().unwrap().cache.borrow();
+        assert_eq!(cache.get(&format!("{root}/repo")), Some(&false));
+    }
+
+    #[test]
+    fn markers_are_looked_up_with_the_given_check() {
+        let set = ExclusionSet::default().with_marker(DEFAULT_MARKER, true);
+        let looked_up = RefCell::new(Vec::new());
+        let unreachable = |path: &str| {
+            looked_up.borrow_mut().push(path.to_string());
+            false
+        };
+        assert!(!set.is_marked("/mnt/nfs/proj", unreachable));
+        let marker = |dir: &str| {
+            Path::new(dir)
+                .join(DEFAULT_MARKER)
+                .to_string_lossy()
+                .into_owned()
+        };
+        assert_eq!(
+            *looked_up.borrow(),
+            [
+                marker("/mnt/nfs/proj"),
+                marker("/mnt/nfs"),
+                marker("/mnt"),
+                marker("/")
+            ]
+        );
+    }
+
+    #[test]
+    fn no_markers_configured() {
+        let (_dir, root) = marked_tree();
+        assert!(!ExclusionSet::default().is_marked(&format!("{root}/repo/vendor"), exists));
+    }
 }
@@ -0,0 +1,437 @@
+//! Checking whether directories still exist without hanging on dead mounts.
+//!
+//! A `stat` on a hung NFS or sshfs mount can block for minutes. Checks run on
+//! a worker thread and give up after a timeout. Once a check on a network or
+//! FUSE mount has timed out, every other directory on it is treated as
+//! missing without another try. On local filesystems, which only stall under
+//! load, checks get twice the timeout and only the directory being checked is
+//! given up on. Directories on filesystem types listed in `skip_fs_types` are
+//! assumed to exist without being checked at all.
+//!
+//! Other lookups beneath a directory, such as marker files or the picker's
+//! preview, go through [`DirCheck::probe`] to get the same protection.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+
+/// Default time to wait for a single existence check.
+pub const DEFAULT_STAT_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Filesystem types that can hang indefinitely when their server goes away,
+/// in addition to every FUSE filesystem (`fuse`, `fuseblk`, `fuse.*`).
+const REMOTE_FS_TYPES: &[&str] = &[
+    "9p",
+    "afs",
+    "autofs",
+    "ceph",
+    "cifs",
+    "davfs",
+    "glusterfs",
+    "lustre",
+    "ncpfs",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+];
+
+/// Linux's list of mounts for the current process.
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// A mounted filesystem.
+#[derive(Debug, PartialEq)]
+struct Mount {
+    point: String,
+    fs_type: String,
+}
+
+/// Checks whether directories exist. See the module docs.
+pub struct DirCheck {
+    /// Give up on a check after this long; `None` checks on the calling
+    /// thread without a limit.
+    timeout: Option<Duration>,
+    skip_fs_types: Vec<String>,
+    mounts: Vec<Mount>,
+    /// Network and FUSE mount points on which a check timed out.
+    dead: RefCell<Vec<String>>,
+    worker: RefCell<Option<Worker>>,
+    /// The existence check run by [`is_dir`](Self::is_dir).
+    stat: fn(&Path) -> bool,
+}
+
+impl DirCheck {
+    /// A check with the given timeout that skips directories on the given
+    /// filesystem types (as named in `/proc/self/mountinfo`, e.g. `nfs4` or
+    /// `fuse.sshfs`). Mounts are only known on Linux; elsewhere only the
+    /// timeout applies.
+    pub fn new(timeout: Option<Duration>, skip_fs_types: &[String]) -> Self {
+        let mountinfo = std::fs::read_to_string(MOUNTINFO_PATH).unwrap_or_default();
+        Self::with_mounts(parse_mountinfo(&mountinfo), timeout, skip_fs_types)
+    }
+
+    fn with_mounts(
+        mounts: Vec<Mount>,
+        timeout: Option<Duration>,
+        skip_fs_types: &[String],
+    ) -> Self {
+        Self {
+            timeout,
+            skip_fs_types: skip_fs_types.to_vec(),
+            mounts,
+            dead: RefCell::new(Vec::new()),
+            worker: RefCell::new(None),
+            stat: |path| path.is_dir(),
+        }
+    }
+
+    /// Whether `dir` is an existing directory. Directories on skipped
+    /// filesystem types count as existing; directories whose check timed out,
+    /// or on a network or FUSE mount where an earlier check timed out, count
+    /// as missing.
+    pub fn is_dir(&self, dir: &str) -> bool {
+        if self.is_skipped(dir) {
+            return true;
+        }
+        self.probe(dir, self.stat).unwrap_or(false)
+    }
+
+    /// Whether `path` exists. Paths on skipped filesystem types, or whose
+    /// check timed out, count as missing.
+    pub fn exists(&self, path: &str) -> bool {
+        self.probe(path, |path| path.exists()).unwrap_or(false)
+    }
+
+    /// Run `probe` on `path` with the timeout and dead-mount tracking of
+    /// [`is_dir`](Self::is_dir). `None` if `path` is on a skipped filesystem
+    /// type or a dead mount, or if `probe` timed out.
+    pub fn probe<T: Send + 'static>(
+        &self,
+        path: &str,
+        probe: impl FnOnce(&Path) -> T + Send + 'static,
+    ) -> Option<T> {
+        let mount = self.mount_of(path);
+        if self.is_skipped(path) || mount.is_some_and(|m| self.dead.borrow().contains(&m.point)) {
+            return None;
+        }
+        let Some(timeout) = self.timeout else {
+            return Some(probe(Path::new(path)));
+        };
+        let remote = mount.filter(|m| is_remote(&m.fs_type));
+        let timeout = if remote.is_some() {
+            timeout
+        } else {
+            timeout * 2
+        };
+        let result = self.run_with_timeout(path, probe, timeout);
+        if result.is_none() {
+            if let Some(mount) = remote {
+                self.dead.borrow_mut().push(mount.point.clone());
+            }
+        }
+        result
+    }
+
+    /// Whether `path` is on one of the skipped filesystem types.
+    fn is_skipped(&self, path: &str) -> bool {
+        self.mount_of(path)
+            .is_some_and(|m| self.skip_fs_types.contains(&m.fs_type))
+    }
+
+    /// Run `probe` on the worker thread. `None` if it timed out, in which
+    /// case the worker is abandoned and a new one started for the next check.
+    fn run_with_timeout<T: Send + 'static>(
+        &self,
+        path: &str,
+        probe: impl FnOnce(&Path) -> T + Send + 'static,
+        timeout: Duration,
+    ) -> Option<T> {
+        let mut worker = self.worker.borrow_mut();
+        if worker.is_none() {
+            match Worker::spawn() {
+                Some(spawned) => *worker = Some(spawned),
+                // Without a thread there is no timeout either.
+                None => return Some(probe(Path::new(path))),
+            }
+        }
+        let result = worker
+            .as_ref()
+            .and_then(|w| w.run(PathBuf::from(path), probe, timeout));
+        if result.is_none() {
+            *worker = None;
+        }
+        result
+    }
+
+    /// The mount `dir` is on: the one with the longest mount point that is
+    /// `dir` or one of its ancestors.
+    fn mount_of(&self, dir: &str) -> Option<&Mount> {
+        self.mounts
+            .iter()
+            .filter(|m| is_within(dir, &m.point))
+            .max_by_key(|m| m.point.len())
+    }
+}
+
+/// A probe queued for the worker thread, which sends its own result back.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A thread that runs probes one at a time.
+struct Worker {
+    jobs: Sender<Job>,
+}
+
+impl Worker {
+    fn spawn() -> Option<Self> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        std::thread::Builder::new()
+            .name("stat".to_string())
+            .spawn(move || {
+                for job in queue {
+                    job();
+                }
+            })
+            .ok()?;
+        Some(Self { jobs })
+    }
+
+    fn run<T: Send + 'static>(
+        &self,
+        path: PathBuf,
+        probe: impl FnOnce(&Path) -> T + Send + 'static,
+        timeout: Duration,
+    ) -> Option<T> {
+        let (sender, result) = mpsc::channel();
+        let job = move || {
+            let _ = sender.send(probe(&path));
+        };
+        self.jobs.send(Box::new(job)).ok()?;
+        result.recv_timeout(timeout).ok()
+    }
+}
+
+/// Whether `fs_type` is a network or FUSE filesystem.
+fn is_remote(fs_type: &str) -> bool {
+    matches!(fs_type, "fuse" | "fuseblk")
+        || fs_type.starts_with("fuse.")
+        || REMOTE_FS_TYPES.contains(&fs_type)
+}
+
+/// Whether `dir` is `ancestor` or lies beneath it.
+fn is_within(dir: &str, ancestor: &str) -> bool {
+    match dir.strip_prefix(ancestor) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || ancestor.ends_with('/'),
+        None => false,
+    }
+}
+
+/// Parse the mount points and filesystem types out of `/proc/self/mountinfo`.
+///
+/// Each line is `id parent major:minor root mount-point options
+/// [optional fields...] - fs-type source super-options`, with spaces and
+/// other special characters in paths escaped as octal (`\040`).
+fn parse_mountinfo(content: &str) -> Vec<Mount> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (fields, rest) = line.split_once(" - ")?;
+            let point = fields.split(' ').nth(4)?;
+            let fs_type = rest.split(' ').next()?;
+            Some(Mount {
+                point: unescape_octal(point),
+                fs_type: fs_type.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn unescape_octal(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).filter(|_| bytes[i] == b'\\');
+        match escape
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok())
+        {
+            Some(byte) => {
+                out.push(byte);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    const MOUNTINFO: &str = "\
+22 1 0:21 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 0:32 / /mnt/nfs rw,relatime shared:20 - nfs4 server:/export rw,vers=4.2
+36 22 0:33 / /mnt/My\\040Drive rw,relatime - fuse.sshfs user@host: rw
+37 35 0:34 / /mnt/nfs/local rw - tmpfs tmpfs rw
+";
+
+    fn mounts() -> Vec<Mount> {
+        parse_mountinfo(MOUNTINFO)
+    }
+
+    /// Pretends everything exists, but hangs on the NFS mount.
+    fn hanging_stat(path: &Path) -> bool {
+        if path.starts_with("/mnt/nfs") && !path.starts_with("/mnt/nfs/local") {
+            std::thread::sleep(Duration::from_secs(5));
+        }
+        true
+    }
+
+    /// Pretends everything exists, but is slow for `/slow` and a little slow
+    /// for `/sluggish`.
+    fn slow_stat(path: &Path) -> bool {
+        if path.starts_with("/slow") {
+            std::thread::sleep(Duration::from_millis(500));
+        } else if path.starts_with("/sluggish") {
+            std::thread::sleep(Duration::from_millis(70));
+        }
+        true
+    }
+
+    #[test]
+    fn parses_mountinfo() {
+        assert_eq!(
+            mounts(),
+            vec![
+                Mount {
+                    point: "/".into(),
+                    fs_type: "ext4".into()
+                },
+                Mount {
+                    point: "/mnt/nfs".into(),
+                    fs_type: "nfs4".into()
+                },
+                Mount {
+                    point: "/mnt/My Drive".into(),
+                    fs_type: "fuse.sshfs".into()
+                },
+                Mount {
+                    point: "/mnt/nfs/local".into(),
+                    fs_type: "tmpfs".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_innermost_mount() {
+        let check = DirCheck::with_mounts(mounts(), None, &[]);
+        let fs_type = |dir| check.mount_of(dir).map(|m| m.fs_type.as_str());
+        assert_eq!(fs_type("/home"), Some("ext4"));
+        assert_eq!(fs_type("/mnt/nfs"), Some("nfs4"));
+        assert_eq!(fs_type("/mnt/nfs/a/b"), Some("nfs4"));
+        assert_eq!(fs_type("/mnt/nfs2"), Some("ext4"));
+        assert_eq!(fs_type("/mnt/nfs/local/x"), Some("tmpfs"));
+        assert_eq!(fs_type("/mnt/My Drive/docs"), Some("fuse.sshfs"));
+    }
+
+    #[test]
+    fn skipped_fs_types_are_assumed_to_exist() {
+        let check = DirCheck::with_mounts(mounts(), None, &["nfs4".to_string()]);
+        assert!(check.is_dir("/mnt/nfs/does/not/exist"));
+        assert!(!check.is_dir("/mnt/nfs/local/does/not/exist"));
+        assert!(!check.is_dir("/does/not/exist"));
+    }
+
+    #[test]
+    fn checks_real_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        for timeout in [None, Some(DEFAULT_STAT_TIMEOUT)] {
+            let check = DirCheck::new(timeout, &[]);
+            assert!(check.is_dir(path));
+            assert!(!check.is_dir(&format!("{path}/missing")));
+        }
+    }
+
+    #[test]
+    fn hung_mount_times_out_once() {
+        let check = DirCheck {
+            stat: hanging_stat,
+            ..DirCheck::with_mounts(mounts(), Some(Duration::from_millis(50)), &[])
+        };
+        let start = std::time::Instant::now();
+        assert!(!check.is_dir("/mnt/nfs/a"));
+        assert!(!check.is_dir("/mnt/nfs/b"));
+        assert!(!check.is_dir("/mnt/nfs/c"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // Other mounts are still checked, by a fresh worker.
+        assert!(check.is_dir("/home"));
+        assert!(check.is_dir("/mnt/nfs/local/x"));
+    }
+
+    #[test]
+    fn slow_local_check_does_not_kill_the_mount() {
+        let check = DirCheck {
+            stat: slow_stat,
+            ..DirCheck::with_mounts(mounts(), Some(Duration::from_millis(50)), &[])
+        };
+        // Local filesystems get twice the timeout.
+        assert!(check.is_dir("/sluggish"));
+        // A check that still times out only gives up on that directory.
+        assert!(!check.is_dir("/slow"));
+        assert!(check.is_dir("/home"));
+        assert!(check.dead.borrow().is_empty());
+    }
+
+    #[test]
+    fn probes_skip_skipped_and_dead_mounts() {
+        let check = DirCheck {
+            stat: hanging_stat,
+            ..DirCheck::with_mounts(
+                mounts(),
+                Some(Duration::from_millis(50)),
+                &["fuse.sshfs".to_string()],
+            )
+        };
+        let ran = Arc::new(AtomicBool::new(false));
+        let probe = |ran: &Arc<AtomicBool>| {
+            let ran = Arc::clone(ran);
+            move |_: &Path| ran.store(true, Ordering::SeqCst)
+        };
+        assert_eq!(check.probe("/mnt/My Drive/docs", probe(&ran)), None);
+        assert!(!check.exists("/mnt/My Drive/docs/.atuin-z-ignore"));
+        assert!(!check.is_dir("/mnt/nfs/a"));
+        assert_eq!(check.probe("/mnt/nfs/b", probe(&ran)), None);
+        assert!(!ran.load(Ordering::SeqCst));
+        assert_eq!(check.probe("/home", probe(&ran)), Some(()));
+        assert!(ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn hung_probe_kills_the_mount() {
+        let check = DirCheck::with_mounts(mounts(), Some(Duration::from_millis(50)), &[]);
+        let hang = |_: &Path| std::thread::sleep(Duration::from_secs(5));
+        let start = std::time::Instant::now();
+        assert_eq!(check.probe("/mnt/nfs/a/.atuin-z-ignore", hang), None);
+        assert_eq!(check.probe("/mnt/nfs/b", hang), None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(*check.dead.borrow(), ["/mnt/nfs"]);
+    }
+
+    #[test]
+    fn recognises_remote_fs_types() {
+        for fs_type in ["nfs4", "cifs", "fuse.sshfs", "fuse", "fuseblk", "autofs"] {
+            assert!(is_remote(fs_type), "{fs_type}");
+        }
+        for fs_type in ["ext4", "btrfs", "tmpfs", "fusectl", "overlay"] {
+            assert!(!is_remote(fs_type), "{fs_type}");
+        }
+    }
+}
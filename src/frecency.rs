@@ -5,34 +5,226 @@ const HOUR_NS: i64 = 3600 * NANOS_PER_SECOND;
 const DAY_NS: i64 = 24 * HOUR_NS;
 const WEEK_NS: i64 = 7 * DAY_NS;
 
+/// Upper age bounds (exclusive) of the per-visit histogram buckets. Visits
+/// older than the last bound fall into a final catch-all bucket.
+pub const HISTOGRAM_EDGES_NS: [i64; 6] = [
+    HOUR_NS,
+    DAY_NS,
+    WEEK_NS,
+    30 * DAY_NS,
+    90 * DAY_NS,
+    365 * DAY_NS,
+];
+
+/// Number of buckets in [`crate::db::DirEntry::histogram`].
+pub const HISTOGRAM_BUCKETS: usize = HISTOGRAM_EDGES_NS.len() + 1;
+
+/// Upper bounds (exclusive) of the command duration classes: 1s, 3s, 7s, ...
+/// up to about 34 minutes, so class `k` holds the durations whose
+/// `log2(1 + seconds)` rounds down to `k`. Longer commands fall into a final
+/// catch-all class.
+pub const DURATION_EDGES_NS: [i64; 11] = {
+    let mut edges = [0; 11];
+    let mut k = 0;
+    while k < edges.len() {
+        edges[k] = ((2 << k) - 1) * NANOS_PER_SECOND;
+        k += 1;
+    }
+    edges
+};
+
+/// Number of classes in [`crate::db::DirEntry::durations`].
+pub const DURATION_CLASSES: usize = DURATION_EDGES_NS.len() + 1;
+
+/// Number of recency buckets used by [`Mode::Frecency`].
+pub const FRECENCY_BUCKETS: usize = 4;
+
+/// Default half-life for [`Mode::Decay`].
+pub const DEFAULT_HALF_LIFE_NS: i64 = 3 * DAY_NS;
+
 /// Scoring mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Mode {
     /// Frequency weighted by recency bucket (default).
     Frecency,
+    /// Frequency weighted by smooth exponential decay of the last visit. The
+    /// weight halves every `half_life_ns`.
+    Decay { half_life_ns: i64 },
+    /// Sum of per-visit weights, each weighted by the age of that visit.
+    PerVisit,
     /// Score = count (frequency only).
     Frequency,
     /// Score = last_visit timestamp (recency only).
     Recency,
 }
 
-/// Score a directory entry.
-pub fn score(entry: &DirEntry, now_ns: i64, mode: &Mode) -> f64 {
+/// Tunable weights used by the scoring modes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weights {
+    /// Exclusive upper age bounds of the frecency buckets, ascending. Visits
+    /// older than the last threshold fall into the final bucket.
+    pub bucket_thresholds_ns: [i64; FRECENCY_BUCKETS - 1],
+    /// Weight of each frecency bucket, newest first. The first weight is also
+    /// the weight of a just-visited directory in [`Mode::Decay`], so scores
+    /// from the two modes are on a comparable scale.
+    pub bucket_weights: [f64; FRECENCY_BUCKETS],
+    /// Per-visit weight for each histogram bucket in [`Mode::PerVisit`].
+    pub visit_weights: [f64; HISTOGRAM_BUCKETS],
+    /// How much a command run on the current host counts relative to one
+    /// run elsewhere. `1.0` disables the boost.
+    pub host_boost: f64,
+    /// Score multiplier for directories used in the current or an active
+    /// session. `1.0` disables the boost.
+    pub session_boost: f64,
+    /// How much a failed command counts relative to a successful one.
+    pub failed_weight: f64,
+    /// Strength of the boost for directories with long-running commands.
+    /// `0.0` disables it. See [`duration_boost`].
+    pub duration_weight: f64,
+}
+
+/// The built-in weights. The first four visit weights match the frecency
+/// buckets; older visits keep halving so that old bursts of activity fade out
+/// instead of counting at a flat 0.25.
+pub const DEFAULT_WEIGHTS: Weights = Weights {
+    bucket_thresholds_ns: [HOUR_NS, DAY_NS, WEEK_NS],
+    bucket_weights: [4.0, 2.0, 0.5, 0.25],
+    visit_weights: [4.0, 2.0, 0.5, 0.25, 0.125, 0.0625, 0.03125],
+    host_boost: 1.0,
+    session_boost: 1.0,
+    failed_weight: 1.0,
+    duration_weight: 0.0,
+};
+
+impl Default for Weights {
+    fn default() -> Self {
+        DEFAULT_WEIGHTS
+    }
+}
+
+/// The factors that make up a directory's score. See [`breakdown`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakdown {
+    /// The mode's score before context boosts.
+    pub base: f64,
+    /// In [`Mode::Frecency`], the recency bucket of the last visit.
+    pub bucket: Option<usize>,
+    /// The weight each visit got for recency: the bucket weight in
+    /// [`Mode::Frecency`], the decayed weight in [`Mode::Decay`].
+    pub recency_weight: Option<f64>,
+    /// Multiplier for commands run on the current host.
+    pub host: f64,
+    /// Multiplier for failed commands.
+    pub failed: f64,
+    /// Multiplier for long-running commands.
+    pub duration: f64,
+    /// Multiplier for use in the current or an active session.
+    pub session: f64,
+}
+
+impl Breakdown {
+    /// The score: the base times all boosts.
+    pub fn score(&self) -> f64 {
+        self.base * self.host * self.failed * self.duration * self.session
+    }
+}
+
+/// Score a directory entry, keeping each factor. [`Breakdown::score`] gives
+/// the score.
+///
+/// Context boosts scale the mode's score, except in [`Mode::Recency`], whose
+/// score is a timestamp.
+pub fn breakdown(entry: &DirEntry, now_ns: i64, mode: &Mode, weights: &Weights) -> Breakdown {
+    let (base, bucket, recency_weight) = mode_score(entry, now_ns, mode, weights);
+    let mut breakdown = Breakdown {
+        base,
+        bucket,
+        recency_weight,
+        host: 1.0,
+        failed: 1.0,
+        duration: 1.0,
+        session: 1.0,
+    };
+    if *mode == Mode::Recency {
+        return breakdown;
+    }
+    breakdown.host = share_boost(entry.local_freq, entry.freq, weights.host_boost);
+    breakdown.failed = share_boost(entry.failed_freq, entry.freq, weights.failed_weight);
+    breakdown.duration = duration_boost(entry, weights.duration_weight);
+    // Unlike the host boost, this does not depend on how much of the history
+    // is from the session: a checkout in use right now should win over a
+    // sibling with far more history overall.
+    if entry.session_freq > 0 {
+        breakdown.session = weights.session_boost;
+    }
+    breakdown
+}
+
+/// Multiplier for a directory where `part` of its `total` commands get
+/// `boost` times the weight of the others.
+fn share_boost(part: i64, total: i64, boost: f64) -> f64 {
+    if total <= 0 {
+        return 1.0;
+    }
+    1.0 + (boost - 1.0) * part as f64 / total as f64
+}
+
+/// Multiplier `1 + weight * log2(1 + median command duration in seconds)`,
+/// with the logarithm rounded down to the median's duration class.
+///
+/// The median rather than the total or the mean keeps this independent of
+/// frequency, which the modes already count, and keeps one long editor
+/// session or hung command from lifting a directory of quick ones. A
+/// directory of 1-minute builds gets 5x `weight`, one of `ls` and `cd` none.
+fn duration_boost(entry: &DirEntry, weight: f64) -> f64 {
+    if weight == 0.0 || entry.freq <= 0 {
+        return 1.0;
+    }
+    let mut shorter = 0;
+    for (class, count) in entry.durations.iter().enumerate() {
+        shorter += count;
+        if 2 * shorter >= entry.freq {
+            return 1.0 + weight * class as f64;
+        }
+    }
+    1.0
+}
+
+/// The mode's score, with the recency bucket and weight it used, if any.
+fn mode_score(
+    entry: &DirEntry,
+    now_ns: i64,
+    mode: &Mode,
+    weights: &Weights,
+) -> (f64, Option<usize>, Option<f64>) {
     match mode {
         Mode::Frecency => {
             let age = now_ns.saturating_sub(entry.last_visit_ns);
-            let weight = if age < HOUR_NS {
-                4.0
-            } else if age < DAY_NS {
-                2.0
-            } else if age < WEEK_NS {
-                0.5
-            } else {
-                0.25
-            };
-            entry.freq as f64 * weight
+            let bucket = weights
+                .bucket_thresholds_ns
+                .iter()
+                .position(|&threshold| age < threshold)
+                .unwrap_or(FRECENCY_BUCKETS - 1);
+            let weight = weights.bucket_weights[bucket];
+            (entry.freq as f64 * weight, Some(bucket), Some(weight))
+        }
+        Mode::Decay { half_life_ns } => {
+            let age = now_ns.saturating_sub(entry.last_visit_ns).max(0);
+            let half_lives = age as f64 / (*half_life_ns).max(1) as f64;
+            let weight = weights.bucket_weights[0] * 0.5f64.powf(half_lives);
+            (entry.freq as f64 * weight, None, Some(weight))
         }
-        Mode::Frequency => entry.freq as f64,
-        Mode::Recency => entry.last_visit_ns as f64,
+        Mode::PerVisit => {
+            let sum = entry
+                .histogram
+                .iter()
+                .zip(weights.visit_weights)
+                .map(|(&count, weight)| count as f64 * weight)
+                .sum();
+            (sum, None, None)
+        }
+        Mode::Frequency => (entry.freq as f64, None, None),
+        Mode::Recency => (entry.last_visit_ns as f64, None, None),
     }
 }
 
@@ -41,12 +233,36 @@ mod tests {
     use super::*;
 
     const NOW: i64 = 1_000_000_000_000_000_000; // 1e18 ns
+    const W: &Weights = &DEFAULT_WEIGHTS;
+
+    /// The score with the default weights.
+    fn score(entry: &DirEntry, now_ns: i64, mode: &Mode) -> f64 {
+        breakdown(entry, now_ns, mode, W).score()
+    }
 
     fn make_entry(freq: i64, last_visit_ns: i64) -> DirEntry {
         DirEntry {
             cwd: "/test".to_string(),
             freq,
             last_visit_ns,
+            local_freq: 0,
+            session_freq: 0,
+            failed_freq: 0,
+            durations: [0; DURATION_CLASSES],
+            histogram: [0; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    fn make_hist_entry(histogram: [i64; HISTOGRAM_BUCKETS]) -> DirEntry {
+        DirEntry {
+            cwd: "/test".to_string(),
+            freq: histogram.iter().sum(),
+            last_visit_ns: NOW,
+            local_freq: 0,
+            session_freq: 0,
+            failed_freq: 0,
+            durations: [0; DURATION_CLASSES],
+            histogram,
         }
     }
 
@@ -97,6 +313,264 @@ mod tests {
         assert_eq!(score(&entry, NOW, &Mode::Frecency), 2.5); // 10 * 0.25
     }
 
+    #[test]
+    fn breakdown_reports_bucket_and_boosts() {
+        let mut entry = make_entry(10, NOW - DAY_NS);
+        entry.local_freq = 10;
+        entry.session_freq = 1;
+        let weights = Weights {
+            host_boost: 2.0,
+            session_boost: 3.0,
+            ..DEFAULT_WEIGHTS
+        };
+        let b = breakdown(&entry, NOW, &Mode::Frecency, &weights);
+        assert_eq!(b.bucket, Some(2));
+        assert_eq!(b.recency_weight, Some(0.5));
+        assert_eq!(b.base, 5.0);
+        assert_eq!(
+            (b.host, b.failed, b.duration, b.session),
+            (2.0, 1.0, 1.0, 3.0)
+        );
+        assert_eq!(b.score(), 30.0);
+    }
+
+    // --- Decay mode ---
+
+    const DECAY: Mode = Mode::Decay {
+        half_life_ns: DAY_NS,
+    };
+
+    #[test]
+    fn decay_just_visited_gets_peak_weight() {
+        let entry = make_entry(10, NOW);
+        assert_eq!(score(&entry, NOW, &DECAY), 40.0); // 10 * 4
+    }
+
+    #[test]
+    fn decay_halves_every_half_life() {
+        let one = make_entry(10, NOW - DAY_NS);
+        let two = make_entry(10, NOW - 2 * DAY_NS);
+        assert_eq!(score(&one, NOW, &DECAY), 20.0);
+        assert_eq!(score(&two, NOW, &DECAY), 10.0);
+    }
+
+    #[test]
+    fn decay_is_continuous_across_bucket_boundaries() {
+        let before = make_entry(10, NOW - HOUR_NS + 1);
+        let after = make_entry(10, NOW - HOUR_NS);
+        let diff = score(&before, NOW, &DECAY) - score(&after, NOW, &DECAY);
+        assert!(diff.abs() < 1e-9);
+    }
+
+    #[test]
+    fn decay_future_timestamp_is_clamped() {
+        let entry = make_entry(10, NOW + DAY_NS);
+        assert_eq!(score(&entry, NOW, &DECAY), 40.0);
+    }
+
+    // --- Per-visit mode ---
+
+    #[test]
+    fn per_visit_sums_bucket_weights() {
+        let entry = make_hist_entry([1, 1, 2, 4, 0, 0, 0]);
+        // 1*4 + 1*2 + 2*0.5 + 4*0.25
+        assert_eq!(score(&entry, NOW, &Mode::PerVisit), 8.0);
+    }
+
+    #[test]
+    fn per_visit_old_burst_fades() {
+        // 500 visits more than a year ago vs. 50 visits this week.
+        let old_burst = make_hist_entry([0, 0, 0, 0, 0, 0, 500]);
+        let recent = make_hist_entry([0, 0, 50, 0, 0, 0, 0]);
+        assert!(score(&recent, NOW, &Mode::PerVisit) > score(&old_burst, NOW, &Mode::PerVisit));
+        // Bucket frecency on the aggregates ranks the old burst first as soon
+        // as it gets a single recent visit; per-visit scoring does not.
+        let revived = make_hist_entry([1, 0, 0, 0, 0, 0, 500]);
+        assert!(score(&revived, NOW, &Mode::Frecency) > score(&recent, NOW, &Mode::Frecency));
+        assert!(score(&recent, NOW, &Mode::PerVisit) > score(&revived, NOW, &Mode::PerVisit));
+    }
+
+    #[test]
+    fn per_visit_empty_histogram_scores_zero() {
+        let entry = make_hist_entry([0; HISTOGRAM_BUCKETS]);
+        assert_eq!(score(&entry, NOW, &Mode::PerVisit), 0.0);
+    }
+
+    // --- Custom weights ---
+
+    #[test]
+    fn frecency_uses_custom_buckets() {
+        let weights = Weights {
+            bucket_thresholds_ns: [HOUR_NS, 2 * DAY_NS, 30 * DAY_NS],
+            bucket_weights: [10.0, 5.0, 1.0, 0.0],
+            ..DEFAULT_WEIGHTS
+        };
+        let entry = make_entry(10, NOW - DAY_NS - 1);
+        assert_eq!(
+            breakdown(&entry, NOW, &Mode::Frecency, &weights).score(),
+            50.0
+        );
+        let entry = make_entry(10, NOW - 60 * DAY_NS);
+        assert_eq!(
+            breakdown(&entry, NOW, &Mode::Frecency, &weights).score(),
+            0.0
+        );
+    }
+
+    #[test]
+    fn decay_peak_follows_first_bucket_weight() {
+        let weights = Weights {
+            bucket_weights: [1.0, 1.0, 1.0, 1.0],
+            ..DEFAULT_WEIGHTS
+        };
+        let entry = make_entry(10, NOW);
+        assert_eq!(breakdown(&entry, NOW, &DECAY, &weights).score(), 10.0);
+    }
+
+    // --- Host boost ---
+
+    #[test]
+    fn host_boost_scales_with_local_share() {
+        let weights = Weights {
+            host_boost: 3.0,
+            ..DEFAULT_WEIGHTS
+        };
+        let remote = make_entry(10, NOW);
+        let mut half_local = make_entry(10, NOW);
+        half_local.local_freq = 5;
+        let mut local = make_entry(10, NOW);
+        local.local_freq = 10;
+        assert_eq!(
+            breakdown(&remote, NOW, &Mode::Frequency, &weights).score(),
+            10.0
+        );
+        assert_eq!(
+            breakdown(&half_local, NOW, &Mode::Frequency, &weights).score(),
+            20.0
+        );
+        assert_eq!(
+            breakdown(&local, NOW, &Mode::Frequency, &weights).score(),
+            30.0
+        );
+        assert_eq!(
+            breakdown(&local, NOW, &Mode::Frecency, &weights).score(),
+            120.0
+        );
+    }
+
+    #[test]
+    fn host_boost_does_not_apply_to_recency() {
+        let weights = Weights {
+            host_boost: 3.0,
+            ..DEFAULT_WEIGHTS
+        };
+        let mut entry = make_entry(10, NOW);
+        entry.local_freq = 10;
+        assert_eq!(
+            breakdown(&entry, NOW, &Mode::Recency, &weights).score(),
+            NOW as f64
+        );
+    }
+
+    // --- Session boost ---
+
+    #[test]
+    fn session_boost_applies_to_any_session_use() {
+        let weights = Weights {
+            session_boost: 10.0,
+            ..DEFAULT_WEIGHTS
+        };
+        let sibling = make_entry(500, NOW);
+        let mut current = make_entry(50, NOW);
+        current.session_freq = 1;
+        assert_eq!(
+            breakdown(&current, NOW, &Mode::Frequency, &weights).score(),
+            500.0
+        );
+        assert_eq!(
+            breakdown(&current, NOW, &Mode::Frecency, &weights).score(),
+            2000.0
+        );
+        assert_eq!(
+            breakdown(&sibling, NOW, &Mode::Frecency, &weights).score(),
+            2000.0
+        );
+        assert_eq!(
+            breakdown(&current, NOW, &Mode::Recency, &weights).score(),
+            NOW as f64
+        );
+    }
+
+    // --- Failed commands ---
+
+    #[test]
+    fn failed_commands_are_down_weighted() {
+        let weights = Weights {
+            failed_weight: 0.25,
+            ..DEFAULT_WEIGHTS
+        };
+        let mut entry = make_entry(10, NOW);
+        entry.failed_freq = 8;
+        // 2 successes + 8 * 0.25
+        assert_eq!(
+            breakdown(&entry, NOW, &Mode::Frequency, &weights).score(),
+            4.0
+        );
+        assert_eq!(score(&entry, NOW, &Mode::Frequency), 10.0);
+    }
+
+    // --- Duration boost ---
+
+    #[test]
+    fn duration_boost_follows_median_duration() {
+        let weights = Weights {
+            duration_weight: 0.5,
+            ..DEFAULT_WEIGHTS
+        };
+        let mut builds = make_entry(4, NOW);
+        builds.durations[2] = 4; // 3s to 7s each
+        let mut quick = make_entry(4, NOW);
+        quick.durations[0] = 4;
+        // 4 * (1 + 0.5 * 2)
+        assert_eq!(
+            breakdown(&builds, NOW, &Mode::Frequency, &weights).score(),
+            8.0
+        );
+        assert_eq!(
+            breakdown(&quick, NOW, &Mode::Frequency, &weights).score(),
+            4.0
+        );
+        assert_eq!(score(&builds, NOW, &Mode::Frequency), 4.0);
+    }
+
+    #[test]
+    fn one_long_command_does_not_set_the_duration_boost() {
+        let weights = Weights {
+            duration_weight: 0.5,
+            ..DEFAULT_WEIGHTS
+        };
+        let mut entry = make_entry(4, NOW);
+        entry.durations[0] = 3;
+        entry.durations[DURATION_CLASSES - 1] = 1; // an hour in an editor
+        assert_eq!(
+            breakdown(&entry, NOW, &Mode::Frequency, &weights).duration,
+            1.0
+        );
+        entry.durations[0] = 2;
+        entry.durations[5] = 1;
+        // The lower of the two middle commands.
+        assert_eq!(
+            breakdown(&entry, NOW, &Mode::Frequency, &weights).duration,
+            1.0
+        );
+        entry.durations[0] = 1;
+        entry.durations[5] = 2;
+        assert_eq!(
+            breakdown(&entry, NOW, &Mode::Frequency, &weights).duration,
+            3.5
+        );
+    }
+
     // --- Frequency mode ---
 
     #[test]
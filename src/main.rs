@@ -1,14 +1,24 @@
+mod atuin;
+mod cache;
 mod cli;
+mod config;
 mod db;
 mod exclusions;
+mod existence;
 mod frecency;
 mod matching;
+mod output;
+mod picker;
 mod shell;
 
 use anyhow::{bail, Result};
 use clap::Parser;
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Maximum number of candidates printed by `atuin-z complete`.
+const COMPLETION_LIMIT: usize = 10;
+
 fn now_ns() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -16,28 +26,112 @@ fn now_ns() -> i64 {
         .as_nanos() as i64
 }
 
-fn main() -> Result<()> {
-    let cli = cli::Cli::parse();
-
-    // Handle `init` subcommand
-    if let Some(cli::Command::Init { shell: s }) = &cli.command {
-        print!("{}", shell::init(s));
-        return Ok(());
+/// Resolve a relative path against the shell's working directory, if known.
+fn absolute_from(path: &str, pwd: Option<&str>) -> String {
+    match pwd {
+        Some(pwd) if !path.starts_with('/') => format!("{}/{}", pwd.trim_end_matches('/'), path),
+        _ => path.to_string(),
     }
+}
 
-    // Handle `-x` / `--exclude`
-    if cli.exclude {
-        if cli.keywords.is_empty() {
-            bail!("atuin-z -x requires a path argument");
+fn manage_exclusions(
+    action: &cli::ExclusionsAction,
+    config: &config::Config,
+    file: &Path,
+) -> Result<()> {
+    match action {
+        cli::ExclusionsAction::List => {
+            let from_file = exclusions::load_exclusions(file)?.into_iter().map(|e| {
+                let note = e
+                    .expires
+                    .map(|t| format!(" (until {})", humantime::format_rfc3339_seconds(t)))
+                    .unwrap_or_default();
+                (e.entry, note)
+            });
+            let from_config = config
+                .exclusions
+                .paths
+                .iter()
+                .map(|e| (e.clone(), " (config)".to_string()));
+            for (entry, note) in from_file.chain(from_config) {
+                let status = match exclusions::status(&entry) {
+                    exclusions::Status::Exists => "exists",
+                    exclusions::Status::Missing => "missing",
+                    exclusions::Status::Pattern => "pattern",
+                };
+                println!("{:<8} {}{}", status, entry, note);
+            }
         }
-        for path in &cli.keywords {
-            exclusions::add(path)?;
+        cli::ExclusionsAction::Remove { entries } => {
+            let pwd = std::env::var("ATUIN_Z_PWD").ok();
+            let targets: Vec<String> = if entries.is_empty() {
+                match &pwd {
+                    Some(pwd) => vec![pwd.clone()],
+                    None => bail!("atuin-z exclusions remove requires a path argument"),
+                }
+            } else {
+                entries
+                    .iter()
+                    .map(|e| match exclusions::Pattern::parse(e) {
+                        exclusions::Pattern::Literal(dir) => absolute_from(&dir, pwd.as_deref()),
+                        _ => e.clone(),
+                    })
+                    .collect()
+            };
+            let removed = exclusions::remove(file, &targets)?;
+            if removed.is_empty() {
+                bail!("not in the exclusion list: {}", targets.join(", "));
+            }
+            for entry in removed {
+                println!("removed {}", entry);
+            }
         }
-        return Ok(());
+        cli::ExclusionsAction::Clear => exclusions::clear(file)?,
+        cli::ExclusionsAction::Edit => exclusions::edit(file)?,
     }
+    Ok(())
+}
 
+/// The history and settings [`matching`] ranks the directories with.
+struct Ranking {
+    entries: Vec<db::DirEntry>,
+    options: matching::Options,
+    exclusions: exclusions::ExclusionSet,
+    dirs: existence::DirCheck,
+    now: i64,
+}
+
+/// Query the database and rank the directories matching `keywords`, keeping
+/// at most `limit` of them.
+fn rank_dirs(
+    cli: &cli::Cli,
+    config: &config::Config,
+    exclusions_file: &Path,
+    keywords: &[String],
+    limit: Option<usize>,
+) -> Result<Vec<matching::ScoredDir>> {
+    let r = load_ranking(cli, config, exclusions_file, keywords)?;
+    Ok(matching::rank(
+        r.entries,
+        keywords,
+        &r.options,
+        r.now,
+        &r.exclusions,
+        &r.dirs,
+        limit,
+    ))
+}
+
+/// Query the database for the directories matching `keywords` and gather
+/// the settings to rank them with.
+fn load_ranking(
+    cli: &cli::Cli,
+    config: &config::Config,
+    exclusions_file: &Path,
+    keywords: &[String],
+) -> Result<Ranking> {
     // Resolve and open DB
-    let db_path = db::resolve_db_path(cli.db.as_deref())?;
+    let db_path = db::resolve_db_path(cli.db.as_deref(), config.atuin.db_path.as_deref())?;
     let conn = db::open(&db_path)?;
 
     // Determine cwd prefix for `-c` flag
@@ -48,30 +142,171 @@ fn main() -> Result<()> {
     };
 
     // Query
-    let entries = db::query_dirs(&conn, cwd_prefix.as_deref())?;
+    let now = now_ns();
+    let host = config.host(cli.host.as_deref());
+    let local_host = db::current_host();
+    let session = std::env::var("ATUIN_SESSION").ok();
+    let commands = config.command_filter()?;
+    let cached = if config.cache.enabled {
+        // The cache only saves time; without it the query still works.
+        match cache::attach(&conn, &config.cache_file()?, &db_path, &commands, now) {
+            Ok(watermark) => Some(watermark),
+            Err(e) => {
+                eprintln!("atuin-z: warning: {e:#}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let entries = db::query_dirs(
+        &conn,
+        &db::Query {
+            cwd_prefix: cwd_prefix.as_deref(),
+            host: host.as_deref(),
+            local_host: Some(&local_host),
+            session: session.as_deref(),
+            active_since_ns: config.active_since_ns(now),
+            successful_only: config.ignore_failed(),
+            commands: Some(&commands),
+            keywords,
+            case_sensitive: config.matching.case_sensitive,
+            cached,
+            ..db::Query::new(now)
+        },
+    )?;
 
     // Determine scoring mode
     let mode = if cli.rank {
         frecency::Mode::Frequency
     } else if cli.time {
         frecency::Mode::Recency
+    } else if cli.per_visit {
+        frecency::Mode::PerVisit
+    } else if cli.decay {
+        frecency::Mode::Decay {
+            half_life_ns: config.half_life_ns(cli.half_life),
+        }
     } else {
-        frecency::Mode::Frecency
+        config.mode(cli.half_life)
     };
+    let options = config.matching_options(mode);
 
     // Load exclusions
-    let exclusion_list = exclusions::load()?;
+    let exclusion_set = config.exclusion_set(&exclusions::load(exclusions_file)?)?;
 
-    // Rank
-    let now = now_ns();
-    let results = matching::rank(entries, &cli.keywords, &mode, now, &exclusion_list);
+    Ok(Ranking {
+        entries,
+        options,
+        exclusions: exclusion_set,
+        dirs: config.dir_check(),
+        now,
+    })
+}
+
+fn main() -> Result<()> {
+    let cli = cli::Cli::parse();
+
+    // Handle `init` subcommand
+    if let Some(cli::Command::Init { shell: s, cmd, cd }) = &cli.command {
+        print!("{}", shell::init(s, cmd, *cd)?);
+        return Ok(());
+    }
+
+    let config = config::load()?;
+    let exclusions_file = config.exclusions_file()?;
 
-    if cli.list {
+    // Handle `exclusions` subcommand
+    if let Some(cli::Command::Exclusions { action }) = &cli.command {
+        return manage_exclusions(action, &config, &exclusions_file);
+    }
+
+    // Handle `-x` / `--exclude`
+    if cli.exclude {
+        let pwd = std::env::var("ATUIN_Z_PWD").ok();
+        let paths = if cli.keywords.is_empty() {
+            match &pwd {
+                Some(pwd) => vec![pwd.clone()],
+                None => bail!("atuin-z -x requires a path argument"),
+            }
+        } else {
+            cli.keywords.clone()
+        };
+        let expires = cli.exclude_for.map(|d| SystemTime::now() + d);
+        let mut patterns = Vec::new();
+        for path in &paths {
+            patterns.push(match exclusions::Pattern::parse(path) {
+                exclusions::Pattern::Literal(dir) if cli.recursive => {
+                    exclusions::Pattern::Tree(absolute_from(&dir, pwd.as_deref()))
+                }
+                exclusions::Pattern::Literal(dir) => {
+                    exclusions::Pattern::Literal(absolute_from(&dir, pwd.as_deref()))
+                }
+                exclusions::Pattern::Glob(_) | exclusions::Pattern::Regex(_) if cli.recursive => {
+                    bail!("--recursive only applies to paths, not to patterns like {path:?}");
+                }
+                pattern => pattern,
+            });
+        }
+        for pattern in &patterns {
+            exclusions::add(&exclusions_file, &pattern.to_entry(), expires)?;
+        }
+        return Ok(());
+    }
+
+    // Handle `explain` subcommand
+    if let Some(cli::Command::Explain { keywords }) = &cli.command {
+        let r = load_ranking(&cli, &config, &exclusions_file, keywords)?;
+        let candidates = matching::explain(
+            r.entries,
+            keywords,
+            &r.options,
+            r.now,
+            &r.exclusions,
+            &r.dirs,
+        );
+        output::write_explanation(&mut std::io::stdout().lock(), &candidates, r.now)?;
+        return Ok(());
+    }
+
+    // Handle hidden `complete` subcommand
+    if let Some(cli::Command::Complete { words }) = &cli.command {
+        let keywords: Vec<String> = words.iter().filter(|w| !w.is_empty()).cloned().collect();
+        let results = rank_dirs(
+            &cli,
+            &config,
+            &exclusions_file,
+            &keywords,
+            Some(COMPLETION_LIMIT),
+        )?;
         for r in &results {
-            println!("{:>10.1}  {}", r.score, r.path);
+            println!("{}", r.path);
+        }
+        return Ok(());
+    }
+
+    // Without a list or picker only the best match is needed, so only as
+    // many directories are checked as it takes to find it.
+    let limit = (!cli.interactive && !cli.list).then_some(1);
+    let results = rank_dirs(&cli, &config, &exclusions_file, &cli.keywords, limit)?;
+    let terminator = if cli.null { b'\0' } else { b'\n' };
+
+    if cli.interactive {
+        if results.is_empty() {
+            bail!("no matching directory");
+        }
+        if let Some(path) = picker::pick(&results, &config.dir_check())? {
+            print!("{}{}", path, terminator as char);
         }
+    } else if cli.list {
+        output::write_list(
+            &mut std::io::stdout().lock(),
+            &results,
+            cli.format,
+            terminator,
+        )?;
     } else if let Some(best) = results.first() {
-        println!("{}", best.path);
+        print!("{}{}", best.path, terminator as char);
     }
 
     Ok(())
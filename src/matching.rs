@@ -1,95 +1,273 @@
 use crate::db::DirEntry;
-use crate::exclusions;
-use crate::frecency::{self, Mode};
+use crate::exclusions::ExclusionSet;
+use crate::existence::DirCheck;
+use crate::frecency::{self, Mode, Weights};
 use std::path::Path;
 
 /// A scored directory result.
 pub struct ScoredDir {
     pub path: String,
     pub score: f64,
+    /// Number of visits counted.
+    pub freq: i64,
+    /// Time of the most recent visit, in nanoseconds since the Unix epoch.
+    pub last_visit_ns: i64,
+    /// Whether the last keyword matched the basename.
+    pub basename_match: bool,
+    /// Whether the directory was visited in the current or an active session.
+    pub session: bool,
+    /// Whether the directory was visited on the current host.
+    pub local: bool,
+}
+
+/// Default score multiplier when the last keyword matches the basename.
+pub const DEFAULT_BASENAME_BOOST: f64 = 1.5;
+
+/// Scoring and matching options for [`rank`].
+pub struct Options {
+    pub mode: Mode,
+    pub weights: Weights,
+    /// Score multiplier when the last keyword matches the basename.
+    pub basename_boost: f64,
+    /// Match keywords case-sensitively.
+    pub case_sensitive: bool,
+}
+
+impl Options {
+    /// Options for `mode` with the built-in weights and matching behaviour.
+    #[cfg(test)]
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            weights: Weights::default(),
+            basename_boost: DEFAULT_BASENAME_BOOST,
+            case_sensitive: false,
+        }
+    }
+}
+
+/// Why a candidate is not among the results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filtered {
+    /// Matched by the exclusion list or Atuin's `cwd_filter`.
+    Excluded,
+    /// Does not exist, or could not be checked in time.
+    Missing,
+    /// Opted out with a marker file.
+    Marked,
+}
+
+/// A directory matching the keywords, with everything that went into its
+/// score.
+pub struct Candidate {
+    pub entry: DirEntry,
+    pub breakdown: frecency::Breakdown,
+    /// The basename boost, if the last keyword matched the basename.
+    pub basename_boost: Option<f64>,
+    /// The final score, as used for ranking.
+    pub score: f64,
+    /// Why the candidate was filtered out, if it was.
+    pub filtered: Option<Filtered>,
+}
+
+impl Candidate {
+    fn into_scored(self) -> ScoredDir {
+        ScoredDir {
+            score: self.score,
+            freq: self.entry.freq,
+            last_visit_ns: self.entry.last_visit_ns,
+            basename_match: self.basename_boost.is_some(),
+            session: self.entry.session_freq > 0,
+            local: self.entry.local_freq > 0,
+            path: self.entry.cwd,
+        }
+    }
 }
 
 /// Filter, score, and rank directory entries against the given keywords.
 ///
-/// Checks that directories exist on disk. See [`rank_with`] for details.
+/// Checks that directories exist on disk, and looks for marker files, with
+/// `dirs`. See [`rank_with`] for details.
 pub fn rank(
     entries: Vec<DirEntry>,
     keywords: &[String],
-    mode: &Mode,
+    options: &Options,
     now_ns: i64,
-    exclusions: &[String],
+    exclusions: &ExclusionSet,
+    dirs: &DirCheck,
+    limit: Option<usize>,
 ) -> Vec<ScoredDir> {
-    rank_with(entries, keywords, mode, now_ns, exclusions, |p| {
-        Path::new(p).is_dir()
-    })
+    rank_with(
+        entries,
+        keywords,
+        options,
+        now_ns,
+        exclusions,
+        limit,
+        |p| dirs.is_dir(p),
+        |p| dirs.exists(p),
+    )
 }
 
 /// Filter, score, and rank directory entries against the given keywords.
 ///
 /// Rules:
-/// - All keywords must match as case-insensitive substrings of the path (AND logic)
+/// - All keywords must match as case-insensitive substrings of the path (AND logic),
+///   or case-sensitive ones if `options.case_sensitive` is set
 /// - Directories where the last keyword matches the basename get a score boost
-/// - Directories that fail `dir_exists` are filtered out
 /// - Excluded directories are filtered out
-fn rank_with<F: Fn(&str) -> bool>(
+/// - Directories that fail `dir_exists` are filtered out, as are those opted
+///   out by a marker file, which is looked for with `path_exists`
+///
+/// The filesystem is only touched for the best-scored candidates, in score
+/// order, until `limit` results are found.
+#[allow(clippy::too_many_arguments)]
+fn rank_with<F: Fn(&str) -> bool, G: Fn(&str) -> bool>(
     entries: Vec<DirEntry>,
     keywords: &[String],
-    mode: &Mode,
+    options: &Options,
     now_ns: i64,
-    exclusions: &[String],
+    exclusions: &ExclusionSet,
+    limit: Option<usize>,
     dir_exists: F,
+    path_exists: G,
 ) -> Vec<ScoredDir> {
-    let keywords_lower: Vec<String> = keywords.iter().map(|k| k.to_lowercase()).collect();
-
-    let mut results: Vec<ScoredDir> = entries
-        .iter()
-        .filter(|e| {
-            // All keywords must match as case-insensitive substrings
-            let path_lower = e.cwd.to_lowercase();
-            keywords_lower.iter().all(|kw| path_lower.contains(kw))
-        })
-        .filter(|e| {
-            // Filter out excluded directories
-            !exclusions::is_excluded(&e.cwd, exclusions)
+    candidates(entries, keywords, options, now_ns, exclusions)
+        .into_iter()
+        .filter(|c| {
+            c.filtered.is_none()
+                && check_on_disk(&c.entry.cwd, exclusions, &dir_exists, &path_exists).is_none()
         })
+        .take(limit.unwrap_or(usize::MAX))
+        .map(Candidate::into_scored)
+        .collect()
+}
+
+/// Like [`rank`], but keep every candidate, with why it was filtered out.
+pub fn explain(
+    entries: Vec<DirEntry>,
+    keywords: &[String],
+    options: &Options,
+    now_ns: i64,
+    exclusions: &ExclusionSet,
+    dirs: &DirCheck,
+) -> Vec<Candidate> {
+    explain_with(
+        entries,
+        keywords,
+        options,
+        now_ns,
+        exclusions,
+        |p| dirs.is_dir(p),
+        |p| dirs.exists(p),
+    )
+}
+
+fn explain_with<F: Fn(&str) -> bool, G: Fn(&str) -> bool>(
+    entries: Vec<DirEntry>,
+    keywords: &[String],
+    options: &Options,
+    now_ns: i64,
+    exclusions: &ExclusionSet,
+    dir_exists: F,
+    path_exists: G,
+) -> Vec<Candidate> {
+    let mut candidates = candidates(entries, keywords, options, now_ns, exclusions);
+    for c in candidates.iter_mut().filter(|c| c.filtered.is_none()) {
+        c.filtered = check_on_disk(&c.entry.cwd, exclusions, &dir_exists, &path_exists);
+    }
+    candidates
+}
+
+/// The entries matching `keywords`, scored and sorted best first. Excluded
+/// directories are marked as filtered; the filesystem is not touched.
+fn candidates(
+    entries: Vec<DirEntry>,
+    keywords: &[String],
+    options: &Options,
+    now_ns: i64,
+    exclusions: &ExclusionSet,
+) -> Vec<Candidate> {
+    let fold = |s: &str| {
+        if options.case_sensitive {
+            s.to_string()
+        } else {
+            s.to_lowercase()
+        }
+    };
+    let keywords_folded: Vec<String> = keywords.iter().map(|k| fold(k)).collect();
+
+    let mut candidates: Vec<Candidate> = entries
+        .into_iter()
         .filter(|e| {
-            // Filter out directories that no longer exist
-            dir_exists(&e.cwd)
+            // All keywords must match as (by default case-insensitive) substrings
+            let path_folded = fold(&e.cwd);
+            keywords_folded.iter().all(|kw| path_folded.contains(kw))
         })
         .map(|e| {
-            let mut s = frecency::score(e, now_ns, mode);
+            let breakdown = frecency::breakdown(&e, now_ns, &options.mode, &options.weights);
+            let mut s = breakdown.score();
 
             // Boost if the last keyword matches the basename
-            if let Some(last_kw) = keywords_lower.last() {
-                if let Some(basename) = Path::new(&e.cwd).file_name() {
-                    if basename.to_string_lossy().to_lowercase().contains(last_kw) {
-                        s *= 1.5;
-                    }
-                }
+            let basename_match = keywords_folded.last().is_some_and(|last_kw| {
+                Path::new(&e.cwd)
+                    .file_name()
+                    .is_some_and(|basename| fold(&basename.to_string_lossy()).contains(last_kw))
+            });
+            if basename_match {
+                s *= options.basename_boost;
             }
 
-            ScoredDir {
-                path: e.cwd.clone(),
+            // Filter out excluded directories
+            let filtered = exclusions.is_excluded(&e.cwd).then_some(Filtered::Excluded);
+
+            Candidate {
+                entry: e,
+                breakdown,
+                basename_boost: basename_match.then_some(options.basename_boost),
                 score: s,
+                filtered,
             }
         })
         .collect();
 
-    results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
-    results
+    candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    candidates
+}
+
+/// Why a directory that passed the other filters is filtered out on disk:
+/// it no longer exists, or it opted out with a marker file.
+fn check_on_disk<F: Fn(&str) -> bool, G: Fn(&str) -> bool>(
+    dir: &str,
+    exclusions: &ExclusionSet,
+    dir_exists: F,
+    path_exists: G,
+) -> Option<Filtered> {
+    if !dir_exists(dir) {
+        Some(Filtered::Missing)
+    } else if exclusions.is_marked(dir, path_exists) {
+        Some(Filtered::Marked)
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::db::DirEntry;
-    use crate::frecency::Mode;
+    use crate::frecency::{Mode, DURATION_CLASSES, HISTOGRAM_BUCKETS};
 
     fn make_entry(cwd: &str, freq: i64, last_visit_ns: i64) -> DirEntry {
         DirEntry {
             cwd: cwd.to_string(),
             freq,
             last_visit_ns,
+            local_freq: 0,
+            session_freq: 0,
+            failed_freq: 0,
+            durations: [0; DURATION_CLASSES],
+            histogram: [0; HISTOGRAM_BUCKETS],
         }
     }
 
@@ -102,7 +280,17 @@ mod tests {
         now_ns: i64,
         exclusions: &[String],
     ) -> Vec<ScoredDir> {
-        rank_with(entries, keywords, mode, now_ns, exclusions, |_| true)
+        let exclusions = ExclusionSet::new(exclusions).unwrap();
+        rank_with(
+            entries,
+            keywords,
+            &Options::new(*mode),
+            now_ns,
+            &exclusions,
+            None,
+            |_| true,
+            |_| false,
+        )
     }
 
     #[test]
@@ -157,15 +345,102 @@ mod tests {
         let results = rank_with(
             entries,
             &[],
-            &Mode::Frequency,
+            &Options::new(Mode::Frequency),
             NOW,
-            &[],
+            &ExclusionSet::default(),
+            None,
             |p| p == "/exists",
+            |_| false,
         );
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].path, "/exists");
     }
 
+    #[test]
+    fn existence_is_checked_lazily_in_score_order() {
+        let entries = vec![
+            make_entry("/third", 10, NOW),
+            make_entry("/gone", 30, NOW),
+            make_entry("/second", 20, NOW),
+        ];
+        let checked = std::cell::RefCell::new(Vec::new());
+        let results = rank_with(
+            entries,
+            &[],
+            &Options::new(Mode::Frequency),
+            NOW,
+            &ExclusionSet::default(),
+            Some(1),
+            |p| {
+                checked.borrow_mut().push(p.to_string());
+                p != "/gone"
+            },
+            |_| false,
+        );
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "/second");
+        assert_eq!(*checked.borrow(), ["/gone", "/second"]);
+    }
+
+    #[test]
+    fn explain_keeps_filtered_candidates_with_reasons() {
+        let entries = vec![
+            make_entry("/proj/gone", 50, NOW),
+            make_entry("/proj/excluded", 40, NOW),
+            make_entry("/proj/best", 30, NOW),
+            make_entry("/proj/second", 20, NOW),
+            make_entry("/other", 100, NOW),
+        ];
+        let keywords: Vec<String> = vec!["proj".into()];
+        let options = Options::new(Mode::Frequency);
+        let exclusions = ExclusionSet::new(&["/proj/excluded".to_string()]).unwrap();
+        let exists = |p: &str| p != "/proj/gone";
+
+        let explained = explain_with(
+            entries.clone(),
+            &keywords,
+            &options,
+            NOW,
+            &exclusions,
+            exists,
+            |_| false,
+        );
+        let outcomes: Vec<(&str, Option<Filtered>)> = explained
+            .iter()
+            .map(|c| (c.entry.cwd.as_str(), c.filtered))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("/proj/gone", Some(Filtered::Missing)),
+                ("/proj/excluded", Some(Filtered::Excluded)),
+                ("/proj/best", None),
+                ("/proj/second", None),
+            ]
+        );
+        assert_eq!(explained[2].breakdown.base, 30.0);
+        assert_eq!(explained[2].basename_boost, None);
+
+        // The results are exactly the unfiltered candidates, with the same scores.
+        let ranked = rank_with(
+            entries,
+            &keywords,
+            &options,
+            NOW,
+            &exclusions,
+            None,
+            exists,
+            |_| false,
+        );
+        let kept: Vec<(&str, f64)> = explained
+            .iter()
+            .filter(|c| c.filtered.is_none())
+            .map(|c| (c.entry.cwd.as_str(), c.score))
+            .collect();
+        let ranked: Vec<(&str, f64)> = ranked.iter().map(|r| (r.path.as_str(), r.score)).collect();
+        assert_eq!(kept, ranked);
+    }
+
     #[test]
     fn basename_match_gets_boost() {
         // Both match keyword "proj", but only the second has "proj" in the basename.
@@ -214,4 +489,136 @@ mod tests {
         let results = rank_all_exist(entries, &[], &Mode::Recency, NOW, &[]);
         assert_eq!(results[0].path, "/new-rare");
     }
+
+    #[test]
+    fn basename_boost_is_configurable() {
+        let entries = vec![
+            make_entry("/home/proj/code", 10, NOW),
+            make_entry("/home/user/proj", 10, NOW),
+        ];
+        let keywords: Vec<String> = vec!["proj".into()];
+        let options = Options {
+            basename_boost: 3.0,
+            ..Options::new(Mode::Frequency)
+        };
+        let results = rank_with(
+            entries,
+            &keywords,
+            &options,
+            NOW,
+            &ExclusionSet::default(),
+            None,
+            |_| true,
+            |_| false,
+        );
+        assert_eq!(results[0].path, "/home/user/proj");
+        assert_eq!(results[0].score, 30.0);
+        assert_eq!(results[1].score, 10.0);
+    }
+
+    #[test]
+    fn case_sensitive_matching() {
+        let entries = vec![
+            make_entry("/home/user/MyProject", 10, NOW),
+            make_entry("/home/user/myproject", 10, NOW),
+        ];
+        let keywords: Vec<String> = vec!["MyProject".into()];
+        let options = Options {
+            case_sensitive: true,
+            ..Options::new(Mode::Frequency)
+        };
+        let results = rank_with(
+            entries,
+            &keywords,
+            &options,
+            NOW,
+            &ExclusionSet::default(),
+            None,
+            |_| true,
+            |_| false,
+        );
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "/home/user/MyProject");
+    }
+
+    #[test]
+    fn sql_keyword_filter_agrees_with_matcher() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_schema(&conn).unwrap();
+        let paths = [
+            "/home/user/Projects/Foo",
+            "/home/user/projects/foo-bar",
+            "/srv/FOO%BAR_baz",
+            "/tmp/Ünïcode/Straße",
+            "/tmp/ÜNÏCODE/STRASSE",
+            "/tmp/\u{212a}elvin",
+            "/tmp/kelvin",
+            "/tmp/ΣΊΣΥΦΟΣ",
+            "/tmp/tab\there",
+            "/",
+        ];
+        for (i, path) in paths.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO history (id, timestamp, duration, exit, command, cwd, session, hostname)
+                 VALUES (?1, 0, 0, 0, 'test', ?2, 'sess', 'host')",
+                rusqlite::params![i.to_string(), path],
+            )
+            .unwrap();
+        }
+
+        let keyword_sets: [&[&str]; 12] = [
+            &[],
+            &["foo"],
+            &["FOO"],
+            &["projects", "foo"],
+            &["%"],
+            &["_"],
+            &["ünï"],
+            &["STRASSE"],
+            &["kelvin"],
+            &["\u{212a}"],
+            &["σοσ"],
+            &["/", ""],
+        ];
+        for case_sensitive in [false, true] {
+            let options = Options {
+                case_sensitive,
+                ..Options::new(Mode::Frequency)
+            };
+            for keywords in keyword_sets {
+                let keywords: Vec<String> = keywords.iter().map(|k| k.to_string()).collect();
+                let rank = |pushed_down: &[String]| -> Vec<String> {
+                    let entries = crate::db::query_dirs(
+                        &conn,
+                        &crate::db::Query {
+                            keywords: pushed_down,
+                            case_sensitive,
+                            ..crate::db::Query::new(NOW)
+                        },
+                    )
+                    .unwrap();
+                    let mut paths: Vec<String> = rank_with(
+                        entries,
+                        &keywords,
+                        &options,
+                        NOW,
+                        &ExclusionSet::default(),
+                        None,
+                        |_| true,
+                        |_| false,
+                    )
+                    .into_iter()
+                    .map(|r| r.path)
+                    .collect();
+                    paths.sort();
+                    paths
+                };
+                assert_eq!(
+                    rank(&keywords),
+                    rank(&[]),
+                    "keywords {keywords:?}, case sensitive: {case_sensitive}"
+                );
+            }
+        }
+    }
 }
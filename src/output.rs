@@ -0,0 +1,338 @@
+//! Printing ranked directories for `-l` in the formats chosen with
+//! `--format`, and score breakdowns for `explain`.
+
+use crate::cli::Format;
+use crate::frecency::FRECENCY_BUCKETS;
+use crate::matching::{Candidate, Filtered, ScoredDir};
+use anyhow::Result;
+use serde::Serialize;
+use std::io::Write;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// One directory in `json`, `jsonl`, and `tsv` output. The field names and
+/// their order are part of the documented output format; only add fields at
+/// the end.
+#[derive(Serialize)]
+struct Record<'a> {
+    path: &'a str,
+    score: f64,
+    freq: i64,
+    /// RFC 3339 timestamp in UTC, e.g. `2024-05-01T12:34:56Z`.
+    last_visit: String,
+    basename_match: bool,
+    session: bool,
+    local: bool,
+}
+
+impl<'a> Record<'a> {
+    fn new(dir: &'a ScoredDir) -> Self {
+        let last_visit = UNIX_EPOCH + Duration::from_nanos(dir.last_visit_ns.max(0) as u64);
+        Self {
+            path: &dir.path,
+            score: dir.score,
+            freq: dir.freq,
+            last_visit: humantime::format_rfc3339_seconds(last_visit).to_string(),
+            basename_match: dir.basename_match,
+            session: dir.session,
+            local: dir.local,
+        }
+    }
+}
+
+/// Write `results` in `format`, ending each record with `terminator`
+/// (normally `\n`, or NUL with `-0`). `json` output is a single record.
+pub fn write_list(
+    out: &mut impl Write,
+    results: &[ScoredDir],
+    format: Format,
+    terminator: u8,
+) -> Result<()> {
+    match format {
+        Format::Plain => {
+            for r in results {
+                write!(out, "{:>10.1}  {}", r.score, r.path)?;
+                out.write_all(&[terminator])?;
+            }
+        }
+        Format::Json => {
+            let records: Vec<Record> = results.iter().map(Record::new).collect();
+            serde_json::to_writer(&mut *out, &records)?;
+            out.write_all(&[terminator])?;
+        }
+        Format::Jsonl => {
+            for r in results {
+                serde_json::to_writer(&mut *out, &Record::new(r))?;
+                out.write_all(&[terminator])?;
+            }
+        }
+        Format::Tsv => {
+            for r in results {
+                let record = Record::new(r);
+                write!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    escape_tsv(record.path),
+                    record.score,
+                    record.freq,
+                    record.last_visit,
+                    record.basename_match,
+                    record.session,
+                    record.local,
+                )?;
+                out.write_all(&[terminator])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write how each candidate was scored, best first. Results are numbered
+/// in rank order; filtered-out candidates show why instead.
+pub fn write_explanation(
+    out: &mut impl Write,
+    candidates: &[Candidate],
+    now_ns: i64,
+) -> Result<()> {
+    if candidates.is_empty() {
+        writeln!(out, "No directories match.")?;
+    }
+    let mut rank = 0;
+    for (i, c) in candidates.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let e = &c.entry;
+        let b = &c.breakdown;
+        match c.filtered {
+            None => {
+                rank += 1;
+                writeln!(out, "#{rank}  {}", e.cwd)?;
+            }
+            Some(reason) => {
+                let reason = match reason {
+                    Filtered::Excluded => "excluded",
+                    Filtered::Missing => "does not exist",
+                    Filtered::Marked => "has a marker file",
+                };
+                writeln!(out, "--  {}  (filtered out: {reason})", e.cwd)?;
+            }
+        }
+        writeln!(out, "    score       {:.1}", c.score)?;
+        writeln!(
+            out,
+            "    visits      {} ({} on this host, {} in session, {} failed)",
+            e.freq, e.local_freq, e.session_freq, e.failed_freq
+        )?;
+        let last_visit = UNIX_EPOCH + Duration::from_nanos(e.last_visit_ns.max(0) as u64);
+        writeln!(
+            out,
+            "    last visit  {} ({} ago)",
+            humantime::format_rfc3339_seconds(last_visit),
+            format_age(now_ns.saturating_sub(e.last_visit_ns))
+        )?;
+        let base = match (b.bucket, b.recency_weight) {
+            (Some(bucket), Some(weight)) => format!(
+                "{:.1} = {} visits x {weight} (recency bucket {} of {FRECENCY_BUCKETS})",
+                b.base,
+                e.freq,
+                bucket + 1
+            ),
+            (None, Some(weight)) => {
+                format!("{:.1} = {} visits x {weight:.3} (decayed)", b.base, e.freq)
+            }
+            _ => format!("{:.1}", b.base),
+        };
+        writeln!(out, "    base        {base}")?;
+        writeln!(
+            out,
+            "    boosts      host x{:.2}, failed x{:.2}, duration x{:.2}, session x{:.2}, basename x{:.2}",
+            b.host,
+            b.failed,
+            b.duration,
+            b.session,
+            c.basename_boost.unwrap_or(1.0)
+        )?;
+    }
+    Ok(())
+}
+
+/// Age in the largest whole unit, e.g. `3h` or `12d`.
+fn format_age(age_ns: i64) -> String {
+    let secs = age_ns.max(0) / 1_000_000_000;
+    match secs {
+        s if s < 60 => format!("{s}s"),
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+/// Escape backslashes, tabs, and line breaks so that a path stays in one
+/// TSV field.
+fn escape_tsv(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> Vec<ScoredDir> {
+        vec![
+            ScoredDir {
+                path: "/home/user/my project".to_string(),
+                score: 12.5,
+                freq: 10,
+                last_visit_ns: 1_714_566_896_000_000_000,
+                basename_match: true,
+                session: false,
+                local: true,
+            },
+            ScoredDir {
+                path: "/tmp/odd\tname\nhere\\".to_string(),
+                score: 0.25,
+                freq: 1,
+                last_visit_ns: 0,
+                basename_match: false,
+                session: true,
+                local: false,
+            },
+        ]
+    }
+
+    fn render(format: Format, terminator: u8) -> String {
+        let mut out = Vec::new();
+        write_list(&mut out, &results(), format, terminator).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn plain_matches_classic_output() {
+        assert_eq!(
+            render(Format::Plain, b'\n'),
+            "      12.5  /home/user/my project\n       0.2  /tmp/odd\tname\nhere\\\n"
+        );
+    }
+
+    #[test]
+    fn json_is_a_single_array() {
+        let out = render(Format::Json, b'\n');
+        assert_eq!(out.lines().count(), 1);
+        let value: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(
+            value[0],
+            serde_json::json!({
+                "path": "/home/user/my project",
+                "score": 12.5,
+                "freq": 10,
+                "last_visit": "2024-05-01T12:34:56Z",
+                "basename_match": true,
+                "session": false,
+                "local": true,
+            })
+        );
+        assert_eq!(value[1]["path"], "/tmp/odd\tname\nhere\\");
+        assert_eq!(value[1]["last_visit"], "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn jsonl_has_one_record_per_line() {
+        let out = render(Format::Jsonl, b'\n');
+        let paths: Vec<String> = out
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["path"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(paths, ["/home/user/my project", "/tmp/odd\tname\nhere\\"]);
+    }
+
+    #[test]
+    fn tsv_escapes_paths() {
+        assert_eq!(
+            render(Format::Tsv, b'\n'),
+            "/home/user/my project\t12.5\t10\t2024-05-01T12:34:56Z\ttrue\tfalse\ttrue\n\
+             /tmp/odd\\tname\\nhere\\\\\t0.25\t1\t1970-01-01T00:00:00Z\tfalse\ttrue\tfalse\n"
+        );
+    }
+
+    #[test]
+    fn nul_terminates_records() {
+        let out = render(Format::Plain, 0);
+        let records: Vec<&str> = out.split_terminator('\0').collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[1].ends_with("/tmp/odd\tname\nhere\\"));
+        assert_eq!(render(Format::Jsonl, 0).matches('\0').count(), 2);
+    }
+
+    #[test]
+    fn explanation_shows_breakdown_and_reasons() {
+        const HOUR: i64 = 3_600_000_000_000;
+        let now = 1_714_566_896_000_000_000;
+        let candidate = |cwd: &str, filtered| Candidate {
+            entry: crate::db::DirEntry {
+                cwd: cwd.to_string(),
+                freq: 10,
+                last_visit_ns: now - 3 * HOUR,
+                local_freq: 4,
+                session_freq: 0,
+                failed_freq: 1,
+                durations: [0; crate::frecency::DURATION_CLASSES],
+                histogram: [0; crate::frecency::HISTOGRAM_BUCKETS],
+            },
+            breakdown: crate::frecency::Breakdown {
+                base: 20.0,
+                bucket: Some(1),
+                recency_weight: Some(2.0),
+                host: 1.2,
+                failed: 1.0,
+                duration: 1.0,
+                session: 1.0,
+            },
+            basename_boost: Some(1.5),
+            score: 36.0,
+            filtered,
+        };
+        let mut out = Vec::new();
+        write_explanation(
+            &mut out,
+            &[
+                candidate("/a/foo", None),
+                candidate("/b/foo", Some(Filtered::Marked)),
+            ],
+            now,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+#1  /a/foo
+    score       36.0
+    visits      10 (4 on this host, 0 in session, 1 failed)
+    last visit  2024-05-01T09:34:56Z (3h ago)
+    base        20.0 = 10 visits x 2 (recency bucket 2 of 4)
+    boosts      host x1.20, failed x1.00, duration x1.00, session x1.00, basename x1.50
+
+--  /b/foo  (filtered out: has a marker file)
+    score       36.0
+    visits      10 (4 on this host, 0 in session, 1 failed)
+    last visit  2024-05-01T09:34:56Z (3h ago)
+    base        20.0 = 10 visits x 2 (recency bucket 2 of 4)
+    boosts      host x1.20, failed x1.00, duration x1.00, session x1.00, basename x1.50
+"
+        );
+    }
+}
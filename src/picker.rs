@@ -0,0 +1,443 @@
+use crate::existence::DirCheck;
+use crate::matching::ScoredDir;
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+use std::fs::File;
+use std::io::Write;
+
+/// Minimum terminal width at which the directory preview is shown.
+const PREVIEW_MIN_WIDTH: usize = 60;
+
+/// What to do after a key press.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Continue,
+    Accept,
+    Cancel,
+}
+
+/// Picker state: the candidates, the live query, and the selection.
+struct Picker<'a> {
+    candidates: &'a [ScoredDir],
+    query: String,
+    /// Indices into `candidates` that match `query`, in rank order.
+    filtered: Vec<usize>,
+    /// Position of the selected entry in `filtered`.
+    selected: usize,
+    /// Position in `filtered` of the first visible row.
+    offset: usize,
+}
+
+impl<'a> Picker<'a> {
+    fn new(candidates: &'a [ScoredDir]) -> Self {
+        let mut picker = Self {
+            candidates,
+            query: String::new(),
+            filtered: Vec::new(),
+            selected: 0,
+            offset: 0,
+        };
+        picker.refilter();
+        picker
+    }
+
+    /// Recompute `filtered`: every whitespace-separated word of the query
+    /// must match as a case-insensitive substring, like `z` keywords.
+    fn refilter(&mut self) {
+        let words: Vec<String> = self
+            .query
+            .split_whitespace()
+            .map(|w| w.to_lowercase())
+            .collect();
+        self.filtered = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                let path = c.path.to_lowercase();
+                words.iter().all(|w| path.contains(w))
+            })
+            .map(|(i, _)| i)
+            .collect();
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    fn selection(&self) -> Option<&'a ScoredDir> {
+        self.filtered
+            .get(self.selected)
+            .map(|&i| &self.candidates[i])
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter => return Action::Accept,
+            KeyCode::Esc => return Action::Cancel,
+            KeyCode::Char('c' | 'g' | 'd') if ctrl => return Action::Cancel,
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Tab => self.move_selection(1),
+            KeyCode::Char('p' | 'k') if ctrl => self.move_selection(-1),
+            KeyCode::Char('n' | 'j') if ctrl => self.move_selection(1),
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.refilter();
+            }
+            KeyCode::Char('w') if ctrl => {
+                let trimmed = self.query.trim_end();
+                let cut = trimmed.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+                self.query.truncate(cut);
+                self.refilter();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.refilter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.refilter();
+            }
+            _ => {}
+        }
+        Action::Continue
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.filtered.is_empty() {
+            return;
+        }
+        let last = self.filtered.len() - 1;
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Adjust `offset` so the selection is within `rows` visible rows.
+    fn scroll_to_selection(&mut self, rows: usize) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+    }
+}
+
+/// Let the user pick one of `candidates` in a full-screen terminal picker.
+///
+/// The picker draws on and reads from the controlling terminal, so it works
+/// inside `$(...)` in shell functions. The preview reads directories through
+/// `dirs`, so a hung mount can't freeze it. Returns `None` if the user
+/// cancels, or without opening the picker if there are no candidates.
+pub fn pick(candidates: &[ScoredDir], dirs: &DirCheck) -> Result<Option<String>> {
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    let tty = std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .context("the interactive picker needs a terminal")?;
+
+    let mut screen = Screen::enter(tty)?;
+    run(&mut screen.tty, &mut Picker::new(candidates), dirs)
+}
+
+/// The terminal in raw mode on the alternate screen. Dropping it restores
+/// the terminal, also when the picker returns an error or panics.
+struct Screen {
+    tty: File,
+}
+
+impl Screen {
+    fn enter(tty: File) -> Result<Self> {
+        terminal::enable_raw_mode().context("failed to enable raw mode")?;
+        let mut screen = Self { tty };
+        execute!(screen.tty, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.tty, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn run(tty: &mut impl Write, picker: &mut Picker, dirs: &DirCheck) -> Result<Option<String>> {
+    loop {
+        draw(tty, picker, dirs)?;
+        let Event::Key(key) = event::read()? else {
+            // Resizes and other events just trigger a redraw.
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        match picker.handle_key(key) {
+            Action::Continue => {}
+            Action::Accept => return Ok(picker.selection().map(|c| c.path.clone())),
+            Action::Cancel => return Ok(None),
+        }
+    }
+}
+
+fn draw(tty: &mut impl Write, picker: &mut Picker, dirs: &DirCheck) -> Result<()> {
+    let (width, height) = terminal::size()?;
+    let (width, height) = (width as usize, height as usize);
+    let rows = height.saturating_sub(1);
+    picker.scroll_to_selection(rows);
+
+    let (list_width, preview_width) = if width >= PREVIEW_MIN_WIDTH {
+        let list = width * 3 / 5;
+        (list, width - list - 3)
+    } else {
+        (width, 0)
+    };
+    let preview = match (preview_width, picker.selection()) {
+        (0, _) | (_, None) => Vec::new(),
+        (_, Some(selected)) => preview_lines(&selected.path, rows, dirs),
+    };
+
+    queue!(
+        tty,
+        terminal::Clear(terminal::ClearType::All),
+        cursor::MoveTo(0, 0)
+    )?;
+    let counter = format!("{}/{}", picker.filtered.len(), picker.candidates.len());
+    let prompt = format!("> {}", picker.query);
+    let gap = width
+        .saturating_sub(prompt.chars().count() + counter.len())
+        .max(1);
+    queue!(
+        tty,
+        Print(fit(&format!("{prompt}{:gap$}{counter}", ""), width))
+    )?;
+
+    for row in 0..rows {
+        queue!(tty, cursor::MoveTo(0, (row + 1) as u16))?;
+        let index = picker.offset + row;
+        if let Some(&i) = picker.filtered.get(index) {
+            let candidate = &picker.candidates[i];
+            let line = format!("{:>10.1}  {}", candidate.score, candidate.path);
+            let is_selected = index == picker.selected;
+            let marker = if is_selected { "> " } else { "  " };
+            if is_selected {
+                queue!(tty, SetAttribute(Attribute::Reverse))?;
+            }
+            let text = fit_path(&line, list_width.saturating_sub(2));
+            let pad = list_width.saturating_sub(2);
+            queue!(tty, Print(format!("{marker}{text:<pad$}")))?;
+            if is_selected {
+                queue!(tty, SetAttribute(Attribute::Reset))?;
+            }
+        } else {
+            queue!(tty, Print(format!("{:list_width$}", "")))?;
+        }
+        if preview_width > 0 {
+            let text = preview.get(row).map_or("", |s| s.as_str());
+            queue!(tty, Print(format!(" │ {}", fit(text, preview_width))))?;
+        }
+    }
+
+    tty.flush()?;
+    Ok(())
+}
+
+/// The first `max` entries of `dir`, directories first and suffixed with `/`.
+/// Nothing is read from skipped filesystem types or dead mounts.
+fn preview_lines(dir: &str, max: usize, dirs: &DirCheck) -> Vec<String> {
+    let listing = dirs.probe(dir, |dir| {
+        std::fs::read_dir(dir).map(|read_dir| {
+            read_dir
+                .filter_map(|e| e.ok())
+                .map(|e| {
+                    let is_dir = e.file_type().is_ok_and(|t| t.is_dir());
+                    (!is_dir, e.file_name().to_string_lossy().to_string())
+                })
+                .collect::<Vec<(bool, String)>>()
+        })
+    });
+    let mut entries = match listing {
+        Some(Ok(entries)) => entries,
+        Some(Err(_)) => return vec!["(cannot read directory)".to_string()],
+        None => return vec!["(not available)".to_string()],
+    };
+    entries.sort();
+    if entries.is_empty() {
+        return vec!["(empty)".to_string()];
+    }
+    entries
+        .into_iter()
+        .take(max)
+        .map(|(is_file, name)| if is_file { name } else { format!("{name}/") })
+        .collect()
+}
+
+/// Truncate `s` to `width` characters.
+fn fit(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
+
+/// Truncate `s` to `width` characters, cutting the middle of the line so the
+/// score and the end of the path stay visible.
+fn fit_path(s: &str, width: usize) -> String {
+    let len = s.chars().count();
+    if len <= width {
+        return s.to_string();
+    }
+    if width < 16 {
+        return fit(s, width);
+    }
+    let head = 12;
+    let tail = width - head - 1;
+    let mut out: String = s.chars().take(head).collect();
+    out.push('…');
+    out.extend(s.chars().skip(len - tail));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirs(paths: &[&str]) -> Vec<ScoredDir> {
+        paths
+            .iter()
+            .enumerate()
+            .map(|(i, p)| ScoredDir {
+                path: p.to_string(),
+                score: (paths.len() - i) as f64,
+                freq: 1,
+                last_visit_ns: 0,
+                basename_match: false,
+                session: false,
+                local: false,
+            })
+            .collect()
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn type_str(picker: &mut Picker, s: &str) {
+        for c in s.chars() {
+            assert_eq!(picker.handle_key(key(KeyCode::Char(c))), Action::Continue);
+        }
+    }
+
+    fn selected(picker: &Picker) -> Option<String> {
+        picker.selection().map(|c| c.path.clone())
+    }
+
+    #[test]
+    fn no_candidates_skips_the_terminal() {
+        assert_eq!(pick(&[], &DirCheck::new(None, &[])).unwrap(), None);
+    }
+
+    #[test]
+    fn starts_with_everything_selected_first() {
+        let candidates = dirs(&["/a", "/b", "/c"]);
+        let picker = Picker::new(&candidates);
+        assert_eq!(picker.filtered, vec![0, 1, 2]);
+        assert_eq!(selected(&picker).as_deref(), Some("/a"));
+    }
+
+    #[test]
+    fn typing_filters_live() {
+        let candidates = dirs(&["/home/Projects/foo", "/home/docs", "/srv/foo"]);
+        let mut picker = Picker::new(&candidates);
+        type_str(&mut picker, "foo");
+        assert_eq!(picker.filtered, vec![0, 2]);
+        type_str(&mut picker, " proj");
+        assert_eq!(picker.filtered, vec![0]);
+        picker.handle_key(key(KeyCode::Backspace));
+        picker.handle_key(ctrl('w'));
+        assert_eq!(picker.query, "foo ");
+        assert_eq!(picker.filtered, vec![0, 2]);
+        picker.handle_key(ctrl('u'));
+        assert_eq!(picker.filtered.len(), 3);
+    }
+
+    #[test]
+    fn arrows_and_ctrl_keys_move_selection() {
+        let candidates = dirs(&["/a", "/b", "/c"]);
+        let mut picker = Picker::new(&candidates);
+        picker.handle_key(key(KeyCode::Down));
+        assert_eq!(selected(&picker).as_deref(), Some("/b"));
+        picker.handle_key(ctrl('n'));
+        picker.handle_key(ctrl('n'));
+        assert_eq!(selected(&picker).as_deref(), Some("/c"));
+        picker.handle_key(ctrl('p'));
+        assert_eq!(selected(&picker).as_deref(), Some("/b"));
+        picker.handle_key(key(KeyCode::Up));
+        picker.handle_key(key(KeyCode::Up));
+        assert_eq!(selected(&picker).as_deref(), Some("/a"));
+    }
+
+    #[test]
+    fn filtering_resets_selection() {
+        let candidates = dirs(&["/a/x", "/b/x", "/c"]);
+        let mut picker = Picker::new(&candidates);
+        picker.handle_key(key(KeyCode::Down));
+        type_str(&mut picker, "x");
+        assert_eq!(selected(&picker).as_deref(), Some("/a/x"));
+    }
+
+    #[test]
+    fn no_matches_selects_nothing() {
+        let candidates = dirs(&["/a"]);
+        let mut picker = Picker::new(&candidates);
+        type_str(&mut picker, "zzz");
+        picker.handle_key(key(KeyCode::Down));
+        assert_eq!(selected(&picker), None);
+    }
+
+    #[test]
+    fn enter_accepts_and_escape_cancels() {
+        let candidates = dirs(&["/a"]);
+        let mut picker = Picker::new(&candidates);
+        assert_eq!(picker.handle_key(key(KeyCode::Enter)), Action::Accept);
+        assert_eq!(picker.handle_key(key(KeyCode::Esc)), Action::Cancel);
+        assert_eq!(picker.handle_key(ctrl('c')), Action::Cancel);
+    }
+
+    #[test]
+    fn scrolling_keeps_selection_visible() {
+        let candidates = dirs(&["/1", "/2", "/3", "/4", "/5"]);
+        let mut picker = Picker::new(&candidates);
+        for _ in 0..4 {
+            picker.handle_key(key(KeyCode::Down));
+        }
+        picker.scroll_to_selection(2);
+        assert_eq!(picker.offset, 3);
+        picker.handle_key(key(KeyCode::Up));
+        picker.handle_key(key(KeyCode::Up));
+        picker.scroll_to_selection(2);
+        assert_eq!(picker.offset, 2);
+    }
+
+    #[test]
+    fn preview_lists_directories_first() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("zdir")).unwrap();
+        std::fs::write(dir.path().join("afile"), "").unwrap();
+        let path = dir.path().to_str().unwrap();
+        let dirs = DirCheck::new(Some(crate::existence::DEFAULT_STAT_TIMEOUT), &[]);
+        assert_eq!(preview_lines(path, 10, &dirs), vec!["zdir/", "afile"]);
+        assert_eq!(preview_lines(path, 1, &dirs), vec!["zdir/"]);
+    }
+
+    #[test]
+    fn fit_path_keeps_both_ends() {
+        let line = "     123.4  /home/user/a/very/long/path/to/project";
+        let fitted = fit_path(line, 30);
+        assert_eq!(fitted.chars().count(), 30);
+        assert!(fitted.starts_with("     123.4  "));
+        assert!(fitted.ends_with("to/project"));
+    }
+}
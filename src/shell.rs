@@ -1,9 +1,356 @@
 use crate::cli::Shell;
+use anyhow::{bail, Result};
 
-pub fn init(shell: &Shell) -> &'static str {
+/// Placeholder for the function name in the script templates.
+const CMD_PLACEHOLDER: &str = "{{cmd}}";
+
+/// Template defining `{{cmd}}` and `{{cmd}}i` for `shell`.
+fn template(shell: &Shell) -> &'static str {
     match shell {
         Shell::Bash => include_str!("shell/bash.sh"),
         Shell::Zsh => include_str!("shell/zsh.sh"),
         Shell::Fish => include_str!("shell/fish.fish"),
+        Shell::Posix => include_str!("shell/posix.sh"),
+        Shell::Nushell => include_str!("shell/nushell.nu"),
+        Shell::Powershell => include_str!("shell/powershell.ps1"),
+        Shell::Elvish => include_str!("shell/elvish.elv"),
+        Shell::Xonsh => include_str!("shell/xonsh.xsh"),
+    }
+}
+
+/// Template replacing `cd` for `shell`. It is appended to the main template
+/// and may use its helpers.
+fn cd_template(shell: &Shell) -> &'static str {
+    match shell {
+        // Everything the bash version uses works the same in zsh.
+        Shell::Bash | Shell::Zsh => include_str!("shell/cd/bash.sh"),
+        Shell::Fish => include_str!("shell/cd/fish.fish"),
+        Shell::Posix => include_str!("shell/cd/posix.sh"),
+        Shell::Nushell => include_str!("shell/cd/nushell.nu"),
+        Shell::Powershell => include_str!("shell/cd/powershell.ps1"),
+        Shell::Elvish => include_str!("shell/cd/elvish.elv"),
+        Shell::Xonsh => include_str!("shell/cd/xonsh.xsh"),
+    }
+}
+
+/// Render the init script for `shell`, naming the functions `cmd` and
+/// `{cmd}i` and, if `cd` is set, replacing `cd` as well.
+pub fn init(shell: &Shell, cmd: &str, cd: bool) -> Result<String> {
+    // POSIX sh only accepts names (`[A-Za-z_][A-Za-z0-9_]*`) as function
+    // names, and bash in POSIX mode agrees.
+    let valid = cmd.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && cmd.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!(
+            "invalid command name {cmd:?}: use letters, digits and '_', not starting with a digit"
+        );
+    }
+    if cmd == "cd" {
+        bail!("use --cd to replace cd");
+    }
+
+    let mut script = template(shell).to_string();
+    if cd {
+        script.push_str(cd_template(shell));
+    }
+    Ok(script.replace(CMD_PLACEHOLDER, cmd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Write the init script for `shell`, both the default one and one with a
+    /// custom name and the `cd` replacement, to a temp file and run the
+    /// command built by `check` on it. Fails if the interpreter is not
+    /// installed; tests for shells that are not commonly installed are
+    /// ignored by default and run with `cargo test -- --ignored`.
+    fn assert_parses(shell: Shell, file_name: &str, check: impl Fn(&Path) -> Command) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(file_name);
+        for (cmd, cd) in [("z", false), ("j", true)] {
+            std::fs::write(&path, init(&shell, cmd, cd).unwrap()).unwrap();
+            let output = match check(&path).output() {
+                Ok(output) => output,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    panic!("interpreter for {file_name} is not installed")
+                }
+                Err(e) => panic!("failed to run interpreter: {e}"),
+            };
+            assert!(
+                output.status.success(),
+                "{file_name} (--cmd {cmd}, cd: {cd}) does not parse:\n{}{}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr),
+            );
+        }
+    }
+
+    /// Run `script` under dash, with the POSIX init script for `cmd` sourced
+    /// and a stub `atuin-z` that prints `target` on `PATH`. Like atuin-z, the
+    /// stub treats a leading `explain` as a subcommand, which prints two
+    /// lines.
+    fn run_dash(cmd: &str, cd: bool, target: &Path, script: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let stub = dir.path().join("atuin-z");
+        let stub_script = format!(
+            "#!/bin/sh\n[ \"$1\" = explain ] && printf 'one\\ntwo\\n' && exit\necho '{}'\n",
+            target.display()
+        );
+        std::fs::write(&stub, stub_script).unwrap();
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let init_path = dir.path().join("init.sh");
+        std::fs::write(&init_path, init(&Shell::Posix, cmd, cd).unwrap()).unwrap();
+
+        let path = format!(
+            "{}:{}",
+            dir.path().display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+        let output = Command::new("dash")
+            .arg("-c")
+            .arg(format!(". \"$1\" && {script}"))
+            .arg("dash")
+            .arg(&init_path)
+            .env("PATH", path)
+            .output()
+            .expect("failed to run dash");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    fn command(program: &str, args: &[&str], path: &Path) -> Command {
+        let mut cmd = Command::new(program);
+        cmd.args(args).arg(path);
+        cmd
+    }
+
+    #[test]
+    fn bash_parses() {
+        assert_parses(Shell::Bash, "init.sh", |p| command("bash", &["-n"], p));
+    }
+
+    #[test]
+    #[ignore = "needs zsh"]
+    fn zsh_parses() {
+        assert_parses(Shell::Zsh, "init.zsh", |p| command("zsh", &["-n"], p));
+    }
+
+    #[test]
+    #[ignore = "needs fish"]
+    fn fish_parses() {
+        assert_parses(Shell::Fish, "init.fish", |p| {
+            command("fish", &["--no-execute"], p)
+        });
+    }
+
+    #[test]
+    fn posix_parses_under_dash() {
+        assert_parses(Shell::Posix, "init.sh", |p| command("dash", &["-n"], p));
+    }
+
+    #[test]
+    fn posix_z_changes_directory_under_dash() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target dir");
+        std::fs::create_dir(&target).unwrap();
+        let script = r#"z foo && pwd && echo "${_atuin_z_result-unset}""#;
+        let stdout = run_dash("z", false, &target, script);
+        assert_eq!(stdout, format!("{}\nunset\n", target.display()));
+    }
+
+    #[test]
+    fn output_arguments_pass_through_anywhere() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        let script = format!(
+            "cd '{}' && z -d --host current explain foo && z foo -l && pwd",
+            dir.path().display()
+        );
+        let stdout = run_dash("z", false, &target, &script);
+        let target = target.display();
+        assert_eq!(
+            stdout,
+            format!("{target}\n{target}\n{}\n", dir.path().display())
+        );
+    }
+
+    #[test]
+    fn subcommand_names_only_pass_through_before_keywords() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        for script in ["z proj explain", "z --host explain proj", "z -- explain"] {
+            let stdout = run_dash("z", false, &target, &format!("{script} && pwd"));
+            assert_eq!(stdout, format!("{}\n", target.display()), "{script}");
+        }
+    }
+
+    #[test]
+    fn output_format_arguments_pass_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        let script = format!(
+            "cd '{}' && z -0 foo && z -l --format json && z --format=tsv -l foo && pwd",
+            dir.path().display()
+        );
+        let stdout = run_dash("z", false, &target, &script);
+        let (target, dir) = (target.display(), dir.path().display());
+        assert_eq!(stdout, format!("{target}\n{target}\n{target}\n{dir}\n"));
+    }
+
+    #[test]
+    fn custom_cmd_renames_functions() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = "j foo && pwd && type ji >/dev/null && ! type z >/dev/null 2>&1";
+        let stdout = run_dash("j", false, dir.path(), script);
+        assert_eq!(stdout, format!("{}\n", dir.path().display()));
+    }
+
+    #[test]
+    fn cd_falls_back_to_frecency_only_for_missing_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        let other = dir.path().join("other");
+        std::fs::create_dir(&target).unwrap();
+        std::fs::create_dir(&other).unwrap();
+        let script = format!("cd '{}' && pwd && cd no-such-dir && pwd", other.display());
+        let stdout = run_dash("z", true, &target, &script);
+        assert_eq!(
+            stdout,
+            format!("{}\n{}\n", other.display(), target.display())
+        );
+    }
+
+    #[test]
+    fn cd_fallback_passes_the_path_as_a_keyword() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        let stdout = run_dash("z", true, &target, "cd explain && pwd");
+        assert_eq!(stdout, format!("{}\n", target.display()));
+    }
+
+    #[test]
+    fn cd_fallback_returns_the_status_of_cd() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let stdout = run_dash("z", true, &missing, "cd elsewhere || echo failed");
+        assert_eq!(stdout, "failed\n");
+    }
+
+    #[test]
+    fn templates_are_fully_rendered() {
+        for shell in [
+            Shell::Bash,
+            Shell::Zsh,
+            Shell::Fish,
+            Shell::Posix,
+            Shell::Nushell,
+            Shell::Powershell,
+            Shell::Elvish,
+            Shell::Xonsh,
+        ] {
+            let script = init(&shell, "j", true).unwrap();
+            assert!(!script.contains("{{"), "{script}");
+            assert!(script.contains("ji"), "{script}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_cmd() {
+        for cmd in ["", "1z", "z z", "z;rm", "cd", "my_z-2"] {
+            assert!(init(&Shell::Bash, cmd, false).is_err(), "{cmd:?}");
+        }
+        assert!(init(&Shell::Bash, "my_z2", false).is_ok());
+    }
+
+    /// Source the init script for `shell` with a custom `--cmd` in `program`,
+    /// which catches invalid function names that a parse-only check misses.
+    fn assert_custom_cmd_sources(shell: Shell, program: &str, args: &[&str]) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("init.sh");
+        for cd in [false, true] {
+            std::fs::write(&path, init(&shell, "my_z2", cd).unwrap()).unwrap();
+            let output = Command::new(program)
+                .args(args)
+                .arg(". \"$1\" && command -v my_z2 && command -v my_z2i")
+                .arg(program)
+                .arg(&path)
+                .output()
+                .unwrap_or_else(|e| panic!("failed to run {program}: {e}"));
+            assert!(
+                output.status.success(),
+                "{program} (--cmd my_z2, cd: {cd}) failed:\n{}",
+                String::from_utf8_lossy(&output.stderr),
+            );
+        }
+    }
+
+    #[test]
+    fn custom_cmd_sources_under_dash() {
+        assert_custom_cmd_sources(Shell::Posix, "dash", &["-c"]);
+    }
+
+    #[test]
+    fn custom_cmd_sources_under_bash() {
+        assert_custom_cmd_sources(Shell::Bash, "bash", &["-c"]);
+        assert_custom_cmd_sources(Shell::Bash, "bash", &["--posix", "-c"]);
+    }
+
+    #[test]
+    #[ignore = "needs nu"]
+    fn nushell_parses() {
+        assert_parses(Shell::Nushell, "init.nu", |p| {
+            let mut cmd = Command::new("nu");
+            cmd.args(["--no-config-file", "-c"])
+                .arg(format!("source '{}'", p.display()));
+            cmd
+        });
+    }
+
+    #[test]
+    #[ignore = "needs pwsh"]
+    fn powershell_parses() {
+        assert_parses(Shell::Powershell, "init.ps1", |p| {
+            let script = format!(
+                "$errors = $null; \
+                 [System.Management.Automation.Language.Parser]::ParseFile('{}', [ref]$null, [ref]$errors) | Out-Null; \
+                 if ($errors) {{ $errors; exit 1 }}",
+                p.display()
+            );
+            let mut cmd = Command::new("pwsh");
+            cmd.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+            cmd
+        });
+    }
+
+    #[test]
+    #[ignore = "needs elvish"]
+    fn elvish_parses() {
+        assert_parses(Shell::Elvish, "init.elv", |p| {
+            command("elvish", &["-compileonly"], p)
+        });
+    }
+
+    #[test]
+    #[ignore = "needs xonsh"]
+    fn xonsh_parses() {
+        // xonsh has no parse-only flag; running the script only defines
+        // functions and aliases.
+        assert_parses(Shell::Xonsh, "init.xsh", |p| {
+            command("xonsh", &["--no-rc"], p)
+        });
     }
 }
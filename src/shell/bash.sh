@@ -1,24 +1,42 @@
-z() {
+{{cmd}}() {
     if [ $# -eq 0 ]; then
         cd ~
         return
     fi
 
-    case "$1" in
-        -x)
-            shift
-            if [ $# -eq 0 ]; then
-                atuin-z -x -- "$PWD"
-            else
-                atuin-z -x -- "$@"
-            fi
-            return
-            ;;
-        -l|-h|--help)
-            ATUIN_Z_PWD="$PWD" atuin-z "$@"
-            return
-            ;;
-    esac
+    # Print instead of changing directory if an option asks for output, or
+    # if the first argument that is not an option or an option's value names
+    # a subcommand. Options may come before or after the keywords.
+    local arg value="" keyword=""
+    for arg in "$@"; do
+        if [ -n "$value" ]; then
+            value=""
+            continue
+        fi
+        case "$arg" in
+            -x|--exclude|-l|--list|-h|--help|-0|--null|--format|--format=*)
+                ATUIN_Z_PWD="$PWD" atuin-z "$@"
+                return
+                ;;
+            --half-life|--host|--for|--db)
+                value=1
+                ;;
+            --)
+                break
+                ;;
+            -*)
+                ;;
+            exclusions|explain|complete)
+                if [ -z "$keyword" ]; then
+                    ATUIN_Z_PWD="$PWD" atuin-z "$@"
+                    return
+                fi
+                ;;
+            *)
+                keyword=1
+                ;;
+        esac
+    done
 
     local result
     result="$(ATUIN_Z_PWD="$PWD" atuin-z "$@")"
@@ -26,3 +44,27 @@ z() {
         cd "$result"
     fi
 }
+
+{{cmd}}i() {
+    local result
+    result="$(ATUIN_Z_PWD="$PWD" atuin-z -i "$@")"
+    if [ -n "$result" ]; then
+        cd "$result"
+    fi
+}
+
+_atuin_z_complete() {
+    # Complete the keywords typed so far to the best-ranked matching
+    # directories, e.g. `z proj<TAB>`.
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    case "$cur" in
+        -*) return ;;
+    esac
+    COMPREPLY=()
+    local dir
+    while IFS= read -r dir; do
+        COMPREPLY+=("$dir")
+    done < <(ATUIN_Z_PWD="$PWD" atuin-z complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null)
+}
+
+complete -o filenames -F _atuin_z_complete {{cmd}} {{cmd}}i
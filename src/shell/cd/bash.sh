@@ -0,0 +1,19 @@
+
+# `cd` that falls back to the best-ranked match when the literal path does not
+# exist, e.g. `cd proj` from anywhere.
+cd() {
+    if [ $# -eq 1 ] && [ ! -d "$1" ]; then
+        case "$1" in
+            -*) ;;
+            *)
+                local result
+                result="$(ATUIN_Z_PWD="$PWD" atuin-z -- "$1" 2>/dev/null)"
+                if [ -n "$result" ]; then
+                    builtin cd "$result"
+                    return
+                fi
+                ;;
+        esac
+    fi
+    builtin cd "$@"
+}
@@ -0,0 +1,21 @@
+
+# `cd` that falls back to the best-ranked match when the literal path does not
+# exist, e.g. `cd proj` from anywhere. Fish's own `cd` function (which keeps
+# the directory history) is kept as `__atuin_z_cd_orig` and called from here.
+if functions -q cd; and not functions -q __atuin_z_cd_orig
+    functions --copy cd __atuin_z_cd_orig
+end
+
+function cd
+    if test (count $argv) -eq 1; and not test -d "$argv[1]"; and not string match -q -- '-*' $argv[1]
+        set -l result (ATUIN_Z_PWD="$PWD" atuin-z -- $argv 2>/dev/null)
+        if test -n "$result"
+            set argv $result
+        end
+    end
+    if functions -q __atuin_z_cd_orig
+        __atuin_z_cd_orig $argv
+    else
+        builtin cd $argv
+    end
+end
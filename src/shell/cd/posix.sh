@@ -0,0 +1,22 @@
+
+# `cd` that falls back to the best-ranked match when the literal path does not
+# exist, e.g. `cd proj` from anywhere. `command cd` bypasses this function.
+cd() {
+    if [ $# -eq 1 ] && [ ! -d "$1" ]; then
+        case "$1" in
+            -*) ;;
+            *)
+                _atuin_z_result="$(ATUIN_Z_PWD="$PWD" atuin-z -- "$1" 2>/dev/null)"
+                if [ -n "$_atuin_z_result" ]; then
+                    # Clean up before `cd`, so that its status is returned.
+                    set -- "$_atuin_z_result"
+                    unset _atuin_z_result
+                    command cd -- "$1"
+                    return
+                fi
+                unset _atuin_z_result
+                ;;
+        esac
+    fi
+    command cd "$@"
+}
@@ -0,0 +1,16 @@
+
+# `cd` that falls back to the best-ranked match when the literal path does not
+# exist, e.g. `cd proj` from anywhere.
+function global:__atuin_z_cd {
+    if ($args.Count -eq 1 -and -not "$($args[0])".StartsWith('-') -and
+        -not (Test-Path -LiteralPath "$($args[0])" -PathType Container)) {
+        $result = __atuin_z_run '--' $args[0] 2>$null | Select-Object -First 1
+        if ($result) {
+            Set-Location -LiteralPath $result
+            return
+        }
+    }
+    Set-Location @args
+}
+
+Set-Alias -Name cd -Value __atuin_z_cd -Option AllScope -Scope Global -Force
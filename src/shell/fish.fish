@@ -1,21 +1,37 @@
-function z
+function {{cmd}}
     if test (count $argv) -eq 0
         cd ~
         return
     end
 
-    switch $argv[1]
-        case -x
-            set -e argv[1]
-            if test (count $argv) -eq 0
-                ATUIN_Z_PWD="$PWD" atuin-z -x -- $PWD
-            else
-                ATUIN_Z_PWD="$PWD" atuin-z -x -- $argv
-            end
-            return
-        case -l -h --help
-            ATUIN_Z_PWD="$PWD" atuin-z $argv
-            return
+    # Print instead of changing directory if an option asks for output, or
+    # if the first argument that is not an option or an option's value names
+    # a subcommand. Options may come before or after the keywords.
+    set -l value 0
+    set -l keyword 0
+    for arg in $argv
+        if test $value -eq 1
+            set value 0
+            continue
+        end
+        if test "$arg" = --
+            break
+        end
+        switch $arg
+            case -x --exclude -l --list -h --help -0 --null --format '--format=*'
+                ATUIN_Z_PWD="$PWD" atuin-z $argv
+                return
+            case --half-life --host --for --db
+                set value 1
+            case '-*'
+            case exclusions explain complete
+                if test $keyword -eq 0
+                    ATUIN_Z_PWD="$PWD" atuin-z $argv
+                    return
+                end
+            case '*'
+                set keyword 1
+        end
     end
 
     set -l result (ATUIN_Z_PWD="$PWD" atuin-z $argv)
@@ -23,3 +39,21 @@ function z
         cd $result
     end
 end
+
+function {{cmd}}i
+    set -l result (ATUIN_Z_PWD="$PWD" atuin-z -i $argv)
+    if test -n "$result"
+        cd $result
+    end
+end
+
+# Complete the keywords typed so far to the best-ranked matching directories,
+# e.g. `z proj<TAB>`.
+function __atuin_z_complete
+    set -l words (commandline -opc)[2..-1] (commandline -ct)
+    string match -q -- '-*' (commandline -ct); and return
+    ATUIN_Z_PWD="$PWD" atuin-z complete -- $words 2>/dev/null
+end
+
+complete -c {{cmd}} -f -a '(__atuin_z_complete)'
+complete -c {{cmd}}i -f -a '(__atuin_z_complete)'
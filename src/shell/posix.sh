@@ -0,0 +1,65 @@
+# POSIX sh has no `local`, so the result goes into a prefixed global that is
+# unset again before returning.
+{{cmd}}() {
+    if [ $# -eq 0 ]; then
+        cd ~
+        return
+    fi
+
+    # Print instead of changing directory if an option asks for output, or
+    # if the first argument that is not an option or an option's value names
+    # a subcommand. Options may come before or after the keywords.
+    _atuin_z_print=
+    _atuin_z_value=
+    _atuin_z_keyword=
+    for _atuin_z_arg in "$@"; do
+        if [ -n "$_atuin_z_value" ]; then
+            _atuin_z_value=
+            continue
+        fi
+        case "$_atuin_z_arg" in
+            -x|--exclude|-l|--list|-h|--help|-0|--null|--format|--format=*)
+                _atuin_z_print=1
+                break
+                ;;
+            --half-life|--host|--for|--db)
+                _atuin_z_value=1
+                ;;
+            --)
+                break
+                ;;
+            -*)
+                ;;
+            exclusions|explain|complete)
+                if [ -z "$_atuin_z_keyword" ]; then
+                    _atuin_z_print=1
+                    break
+                fi
+                ;;
+            *)
+                _atuin_z_keyword=1
+                ;;
+        esac
+    done
+    unset _atuin_z_arg _atuin_z_value _atuin_z_keyword
+    if [ -n "$_atuin_z_print" ]; then
+        unset _atuin_z_print
+        ATUIN_Z_PWD="$PWD" atuin-z "$@"
+        return
+    fi
+    unset _atuin_z_print
+
+    _atuin_z_result="$(ATUIN_Z_PWD="$PWD" atuin-z "$@")"
+    if [ -n "$_atuin_z_result" ]; then
+        cd -- "$_atuin_z_result"
+    fi
+    unset _atuin_z_result
+}
+
+{{cmd}}i() {
+    _atuin_z_result="$(ATUIN_Z_PWD="$PWD" atuin-z -i "$@")"
+    if [ -n "$_atuin_z_result" ]; then
+        cd -- "$_atuin_z_result"
+    fi
+    unset _atuin_z_result
+}
@@ -0,0 +1,55 @@
+function global:__atuin_z_run {
+    $env:ATUIN_Z_PWD = (Get-Location).Path
+    try {
+        & atuin-z @args
+    } finally {
+        Remove-Item Env:ATUIN_Z_PWD -ErrorAction SilentlyContinue
+    }
+}
+
+function global:{{cmd}} {
+    if ($args.Count -eq 0) {
+        Set-Location ~
+        return
+    }
+
+    # Print instead of changing directory if an option asks for output, or
+    # if the first argument that is not an option or an option's value names
+    # a subcommand. Options may come before or after the keywords.
+    $passthrough = '-x', '--exclude', '-l', '--list', '-h', '--help', '-0', '--null', '--format'
+    $withValue = '--half-life', '--host', '--for', '--db'
+    $value = $false
+    $keyword = $false
+    foreach ($arg in $args) {
+        # PowerShell passes arguments that look like numbers as numbers.
+        $word = "$arg"
+        if ($value) {
+            $value = $false
+        } elseif ($word -in $passthrough -or $word -like '--format=*') {
+            __atuin_z_run @args
+            return
+        } elseif ($word -in $withValue) {
+            $value = $true
+        } elseif ($word -eq '--') {
+            break
+        } elseif ($word -notlike '-*') {
+            if (-not $keyword -and $word -in 'exclusions', 'explain', 'complete') {
+                __atuin_z_run @args
+                return
+            }
+            $keyword = $true
+        }
+    }
+
+    $result = __atuin_z_run @args | Select-Object -First 1
+    if ($result) {
+        Set-Location -LiteralPath $result
+    }
+}
+
+function global:{{cmd}}i {
+    $result = __atuin_z_run -i @args | Select-Object -First 1
+    if ($result) {
+        Set-Location -LiteralPath $result
+    }
+}